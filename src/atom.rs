@@ -0,0 +1,399 @@
+use std::fmt;
+use std::str::FromStr;
+
+use version::{self, Version};
+
+/// `!` blocks a package softly, `!!` requires it to be removed before merging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Blocker {
+    Weak,
+    Strong,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Less,
+    LessOrEqual,
+    Equal,
+    /// `=cat/pkg-1.2*`, matching every version that starts with `1.2`.
+    EqualGlob,
+    /// `~cat/pkg-1.2`, matching `1.2` with any revision.
+    Approximate,
+    GreaterOrEqual,
+    Greater,
+}
+
+/// The `:=` and `:*` slot operators, which may follow a slot or stand alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotOperator {
+    Equal,
+    Star,
+}
+
+/// How a USE dependency such as `[foo?]` ties the flag to the flag of the depending package.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UseCondition {
+    /// `[foo]` or `[-foo]`.
+    Always,
+    /// `[foo=]` or `[!foo=]`.
+    Same,
+    /// `[foo?]` or `[!foo?]`.
+    IfEnabled,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UseDep {
+    pub flag: String,
+    /// `false` for `-foo`, `!foo=` and `!foo?`.
+    pub enabled: bool,
+    pub condition: UseCondition,
+    /// The `(+)` or `(-)` default for packages that lack the flag in IUSE.
+    pub default: Option<bool>,
+}
+
+/// A package dependency specification, such as `>=dev-lang/rust-1.26:0/stable::gentoo[-doc,rustfmt?]`.
+#[derive(Clone, Debug)]
+pub struct Atom {
+    pub blocker: Option<Blocker>,
+    pub operator: Option<Operator>,
+    pub category: String,
+    pub name: String,
+    pub version: Option<Version>,
+    pub slot: Option<String>,
+    pub subslot: Option<String>,
+    pub slot_operator: Option<SlotOperator>,
+    pub repository: Option<String>,
+    pub use_deps: Vec<UseDep>,
+}
+
+#[derive(Debug)]
+pub struct ParseAtomError(String);
+
+impl fmt::Display for ParseAtomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid atom: {}", self.0)
+    }
+}
+
+impl fmt::Display for Atom {
+    /// Writes the atom back out in its canonical form, such as `>=dev-lang/rust-1.26:0/stable=::gentoo[-doc,rustfmt?]`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.blocker {
+            Some(Blocker::Weak) => f.write_str("!")?,
+            Some(Blocker::Strong) => f.write_str("!!")?,
+            None => (),
+        }
+        f.write_str(match self.operator {
+            Some(Operator::Less) => "<",
+            Some(Operator::LessOrEqual) => "<=",
+            Some(Operator::Equal) | Some(Operator::EqualGlob) => "=",
+            Some(Operator::Approximate) => "~",
+            Some(Operator::GreaterOrEqual) => ">=",
+            Some(Operator::Greater) => ">",
+            None => "",
+        })?;
+        write!(f, "{}/{}", self.category, self.name)?;
+        if let Some(ref version) = self.version {
+            write!(f, "-{}", version.as_str())?;
+        }
+        if self.operator == Some(Operator::EqualGlob) {
+            f.write_str("*")?;
+        }
+
+        if self.slot.is_some() || self.slot_operator.is_some() {
+            f.write_str(":")?;
+        }
+        if let Some(ref slot) = self.slot {
+            f.write_str(slot)?;
+        }
+        if let Some(ref subslot) = self.subslot {
+            write!(f, "/{}", subslot)?;
+        }
+        match self.slot_operator {
+            Some(SlotOperator::Equal) => f.write_str("=")?,
+            Some(SlotOperator::Star) => f.write_str("*")?,
+            None => (),
+        }
+        if let Some(ref repository) = self.repository {
+            write!(f, "::{}", repository)?;
+        }
+
+        if !self.use_deps.is_empty() {
+            let use_deps: Vec<String> = self.use_deps.iter().map(|use_dep| {
+                let default = match use_dep.default {
+                    Some(true) => "(+)",
+                    Some(false) => "(-)",
+                    None => "",
+                };
+                match (use_dep.condition, use_dep.enabled) {
+                    (UseCondition::Always, true) => format!("{}{}", use_dep.flag, default),
+                    (UseCondition::Always, false) => format!("-{}{}", use_dep.flag, default),
+                    (UseCondition::Same, enabled) => format!("{}{}{}=", if enabled { "" } else { "!" }, use_dep.flag, default),
+                    (UseCondition::IfEnabled, enabled) => format!("{}{}{}?", if enabled { "" } else { "!" }, use_dep.flag, default),
+                }
+            }).collect();
+            write!(f, "[{}]", use_deps.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl Atom {
+    /// Whether a package version with the given `slot` (optionally `slot/subslot`) from `repository` satisfies
+    /// this atom. Blockers and USE dependencies are not taken into account.
+    pub fn matches(&self, category: &str, name: &str, version: &Version, slot: &str, repository: &str) -> bool {
+        if self.category != category || self.name != name {
+            return false;
+        }
+
+        if let Some(ref wanted) = self.repository {
+            if wanted != repository {
+                return false;
+            }
+        }
+
+        let mut slot_split = slot.splitn(2, '/');
+        let (slot, subslot) = (slot_split.next().unwrap_or(""), slot_split.next());
+        if let Some(ref wanted) = self.slot {
+            if wanted != slot {
+                return false;
+            }
+        }
+        if let Some(ref wanted) = self.subslot {
+            if Some(&**wanted) != subslot {
+                return false;
+            }
+        }
+
+        match (self.operator, &self.version) {
+            (Some(operator), Some(wanted)) => match operator {
+                Operator::Less => version < wanted,
+                Operator::LessOrEqual => version <= wanted,
+                Operator::Equal => version == wanted,
+                Operator::EqualGlob => version.as_str().starts_with(wanted.as_str()),
+                Operator::Approximate => version.without_revision() == wanted.without_revision(),
+                Operator::GreaterOrEqual => version >= wanted,
+                Operator::Greater => version > wanted,
+            },
+            _ => true,
+        }
+    }
+}
+
+impl FromStr for Atom {
+    type Err = ParseAtomError;
+
+    fn from_str(text: &str) -> Result<Atom, ParseAtomError> {
+        let error = || ParseAtomError(text.to_owned());
+        let mut rest = text.trim();
+
+        let blocker = if rest.starts_with("!!") {
+            rest = &rest[2..];
+            Some(Blocker::Strong)
+        } else if rest.starts_with('!') {
+            rest = &rest[1..];
+            Some(Blocker::Weak)
+        } else {
+            None
+        };
+
+        let mut operator = None;
+        for &(prefix, op) in &[(">=", Operator::GreaterOrEqual), ("<=", Operator::LessOrEqual), (">", Operator::Greater),
+                               ("<", Operator::Less), ("=", Operator::Equal), ("~", Operator::Approximate)] {
+            if rest.starts_with(prefix) {
+                rest = &rest[prefix.len()..];
+                operator = Some(op);
+                break;
+            }
+        }
+
+        let mut use_deps = Vec::new();
+        if rest.ends_with(']') {
+            let start = rest.find('[').ok_or_else(error)?;
+            for use_dep in rest[start + 1..rest.len() - 1].split(',') {
+                use_deps.push(parse_use_dep(use_dep.trim()).ok_or_else(error)?);
+            }
+            rest = &rest[..start];
+        }
+
+        let mut repository = None;
+        if let Some(index) = rest.find("::") {
+            repository = Some(rest[index + 2..].to_owned());
+            rest = &rest[..index];
+        }
+
+        let (mut slot, mut subslot, mut slot_operator) = (None, None, None);
+        if let Some(index) = rest.find(':') {
+            let mut slot_text = &rest[index + 1..];
+            rest = &rest[..index];
+            if slot_text.ends_with('=') {
+                slot_operator = Some(SlotOperator::Equal);
+                slot_text = &slot_text[..slot_text.len() - 1];
+            } else if slot_text == "*" {
+                slot_operator = Some(SlotOperator::Star);
+                slot_text = "";
+            }
+            if !slot_text.is_empty() {
+                let mut split = slot_text.splitn(2, '/');
+                slot = split.next().map(|slot| slot.to_owned());
+                subslot = split.next().map(|subslot| subslot.to_owned());
+            }
+        }
+
+        if operator == Some(Operator::Equal) && rest.ends_with('*') {
+            operator = Some(Operator::EqualGlob);
+            rest = &rest[..rest.len() - 1];
+        }
+
+        let slash = rest.find('/').ok_or_else(error)?;
+        let (category, pf) = (&rest[..slash], &rest[slash + 1..]);
+        let (name, version) = match operator {
+            Some(_) => {
+                let (name, version) = version::split_pf(pf).ok_or_else(error)?;
+                (name, Some(version))
+            }
+            // Without an operator the name must not end in something that looks like a version.
+            None if version::split_pf(pf).is_some() => return Err(error()),
+            None => (pf, None),
+        };
+
+        if category.is_empty() || name.is_empty() || name.contains('/') {
+            return Err(error());
+        }
+
+        Ok(Atom {
+            blocker,
+            operator,
+            category: category.to_owned(),
+            name: name.to_owned(),
+            version,
+            slot,
+            subslot,
+            slot_operator,
+            repository,
+            use_deps,
+        })
+    }
+}
+
+fn parse_use_dep(text: &str) -> Option<UseDep> {
+    let (mut flag, enabled) = if text.starts_with('-') || text.starts_with('!') {
+        (&text[1..], false)
+    } else {
+        (text, true)
+    };
+
+    let condition = if flag.ends_with('?') {
+        flag = &flag[..flag.len() - 1];
+        UseCondition::IfEnabled
+    } else if flag.ends_with('=') {
+        flag = &flag[..flag.len() - 1];
+        UseCondition::Same
+    } else {
+        UseCondition::Always
+    };
+    match (text.chars().next(), condition) {
+        (Some('!'), UseCondition::Always) => return None,
+        (Some('-'), UseCondition::Same) | (Some('-'), UseCondition::IfEnabled) => return None,
+        _ => (),
+    }
+
+    let default = if flag.ends_with("(+)") {
+        flag = &flag[..flag.len() - 3];
+        Some(true)
+    } else if flag.ends_with("(-)") {
+        flag = &flag[..flag.len() - 3];
+        Some(false)
+    } else {
+        None
+    };
+
+    if flag.is_empty() {
+        None
+    } else {
+        Some(UseDep { flag: flag.to_owned(), enabled, condition, default })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(text: &str) -> Atom {
+        text.parse().unwrap_or_else(|why| panic!("{}", why))
+    }
+
+    fn version(text: &str) -> Version {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_blockers_and_operators() {
+        let strong = atom("!!<app-misc/hello-2.0");
+        assert_eq!(strong.blocker, Some(Blocker::Strong));
+        assert_eq!(strong.operator, Some(Operator::Less));
+        assert_eq!(strong.version.as_ref().map(Version::as_str), Some("2.0"));
+
+        assert_eq!(atom("!app-misc/hello").blocker, Some(Blocker::Weak));
+        assert_eq!(atom("~app-misc/hello-1.0").operator, Some(Operator::Approximate));
+
+        let glob = atom("=dev-lang/rust-1.2*");
+        assert_eq!(glob.operator, Some(Operator::EqualGlob));
+        assert_eq!(glob.version.as_ref().map(Version::as_str), Some("1.2"));
+    }
+
+    #[test]
+    fn parses_slots_and_repositories() {
+        let atom = atom(">=dev-lang/rust-1.26:0/stable=::gentoo");
+        assert_eq!((atom.category.as_str(), atom.name.as_str()), ("dev-lang", "rust"));
+        assert_eq!(atom.slot.as_ref().map(String::as_str), Some("0"));
+        assert_eq!(atom.subslot.as_ref().map(String::as_str), Some("stable"));
+        assert_eq!(atom.slot_operator, Some(SlotOperator::Equal));
+        assert_eq!(atom.repository.as_ref().map(String::as_str), Some("gentoo"));
+    }
+
+    #[test]
+    fn parses_use_dependencies() {
+        let atom = atom("app-misc/hello[!foo=,bar(-)?,-doc,baz(+)]");
+        assert_eq!(atom.use_deps, vec![
+            UseDep { flag: "foo".into(), enabled: false, condition: UseCondition::Same, default: None },
+            UseDep { flag: "bar".into(), enabled: true, condition: UseCondition::IfEnabled, default: Some(false) },
+            UseDep { flag: "doc".into(), enabled: false, condition: UseCondition::Always, default: None },
+            UseDep { flag: "baz".into(), enabled: true, condition: UseCondition::Always, default: Some(true) },
+        ]);
+    }
+
+    #[test]
+    fn rejects_invalid_atoms() {
+        for text in &["app-misc/hello-1.0", "hello", ">=app-misc/hello", "app-misc/hello[!foo]", "app-misc/hello[-foo?]",
+                      "app-misc/hello[]"] {
+            assert!(text.parse::<Atom>().is_err(), "{} parsed", text);
+        }
+    }
+
+    #[test]
+    fn writes_atoms_back_out() {
+        for text in &["!!<app-misc/hello-2.0", "~app-misc/hello-1.0", "=dev-lang/rust-1.2*", "app-misc/hello:*",
+                      ">=dev-lang/rust-1.26:0/stable=::gentoo[-doc,rustfmt?]", "app-misc/hello[!foo=,bar(-)?]"] {
+            assert_eq!(atom(text).to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn matches_versions_slots_and_repositories() {
+        let glob = atom("=app-misc/hello-1.2*");
+        assert!(glob.matches("app-misc", "hello", &version("1.2.3"), "0", "gentoo"));
+        assert!(!glob.matches("app-misc", "hello", &version("1.3"), "0", "gentoo"));
+        assert!(!glob.matches("app-misc", "other", &version("1.2"), "0", "gentoo"));
+
+        let approximate = atom("~app-misc/hello-1.0");
+        assert!(approximate.matches("app-misc", "hello", &version("1.0-r3"), "0", "gentoo"));
+        assert!(!approximate.matches("app-misc", "hello", &version("1.0.1"), "0", "gentoo"));
+
+        let slotted = atom(">=app-misc/hello-2.0_rc1:2/2.1::portix-test");
+        assert!(slotted.matches("app-misc", "hello", &version("2.0"), "2/2.1", "portix-test"));
+        assert!(!slotted.matches("app-misc", "hello", &version("2.0"), "2/2.0", "portix-test"));
+        assert!(!slotted.matches("app-misc", "hello", &version("2.0"), "2/2.1", "gentoo"));
+        assert!(!slotted.matches("app-misc", "hello", &version("1.0"), "2/2.1", "portix-test"));
+    }
+}
@@ -1,426 +1,1320 @@
-extern crate rayon;
-extern crate regex;
 extern crate rusqlite;
 
-use self::rayon::prelude::*;
+use self::rusqlite::{Connection, Row, NO_PARAMS};
+use self::rusqlite::types::ToSql;
 
-use self::regex::Regex;
-
-use self::rusqlite::Connection;
+use atom::Atom;
+use depend::{self, DepSpec};
+use metadata;
+use repos::{self, Repository};
+use scanner::{self, Ebuild};
+use useflags::UseConfig;
+use vdb::{self, ContentsEntry};
+use version::{self, Version};
 
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::io;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::thread;
-use std::sync::{Arc, Mutex};
 
-pub const DB_PATH: &str = "./target/debug/portix.db";
+/// Which columns of the full-text index a search looks at.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SearchScope {
+    Names,
+    Descriptions,
+    NamesAndDescriptions,
+}
+
+/// The last search made from the search entry, which the Search Results view is built from.
+///
+/// Text that parses as an atom, such as `>=dev-lang/rust-1.26`, is matched as one instead.
+#[derive(Clone)]
+pub struct Search {
+    pub text: String,
+    pub scope: SearchScope,
+    pub installed_only: bool,
+}
+
+impl Search {
+    /// An FTS5 query requiring every term of the search, each matched as a prefix within the scoped columns.
+    fn fts_query(&self) -> String {
+        let columns = match self.scope {
+            SearchScope::Names => "name",
+            SearchScope::Descriptions => "description long_description",
+            SearchScope::NamesAndDescriptions => "name description homepage long_description",
+        };
+        self.text.split_whitespace()
+            .map(|term| format!("{{{}}} : \"{}\"*", columns, term.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    }
+
+    /// Restricts results to installed packages when asked to.
+    fn installed_filter(&self) -> &'static str {
+        if self.installed_only { "AND installed_packages.name IS NOT NULL" } else { "" }
+    }
+}
+
+/// The package views selectable from the View combo box, each listing categories (or sets) and their packages.
+#[derive(Clone)]
+pub enum View {
+    All,
+    Installed,
+    Search(Search),
+    Upgradeable,
+    Deprecated,
+    Sets,
+}
+
+/// Why a request from the GUI could not be answered.
+#[derive(Debug)]
+pub enum Error {
+    Sqlite(rusqlite::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Sqlite(ref why) => write!(f, "database error: {}", why),
+            Error::Io(ref why) => write!(f, "{}", why),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(why: rusqlite::Error) -> Error {
+        Error::Sqlite(why)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(why: io::Error) -> Error {
+        Error::Io(why)
+    }
+}
+
+/// A row of the package list.
+pub struct PackageRow {
+    pub category: String,
+    pub name: String,
+    pub installed_version: String,
+    pub recommended_version: String,
+    pub description: String,
+    /// The repository the recommended version comes from.
+    pub repository: String,
+    /// Every available version, newest first.
+    pub versions: Vec<VersionRow>,
+}
+
+impl PackageRow {
+    /// Reads the first six columns of a package list query.
+    fn from_row(row: &Row) -> rusqlite::Result<PackageRow> {
+        Ok(PackageRow {
+            category: row.get(0)?,
+            name: row.get(1)?,
+            installed_version: row.get(2)?,
+            recommended_version: row.get(3)?,
+            description: row.get(4)?,
+            repository: row.get(5)?,
+            versions: Vec::new(),
+        })
+    }
+}
+
+/// A version of a package as provided by one repository.
+pub struct VersionRow {
+    pub version: String,
+    pub slot: String,
+    pub repository: String,
+    /// The keyword for the current architecture, such as `amd64`, `~amd64` or `-*`, if any.
+    pub arch_keyword: String,
+    pub accepted: bool,
+    pub masked: bool,
+    pub installed: bool,
+}
+
+impl VersionRow {
+    /// Why the version cannot be installed as things are configured, if it can't.
+    pub fn mask_status(&self) -> &'static str {
+        if self.masked {
+            "Masked"
+        } else if self.accepted {
+            ""
+        } else if self.arch_keyword.is_empty() {
+            "Missing keyword"
+        } else {
+            "Keyword masked"
+        }
+    }
+}
+
+/// A category, or a set in the Sets view, with the number of packages it holds.
+pub struct CategoryCount {
+    pub category: String,
+    pub count: i32,
+}
+
+impl CategoryCount {
+    fn from_row(row: &Row) -> rusqlite::Result<CategoryCount> {
+        Ok(CategoryCount { category: row.get(0)?, count: row.get(1)? })
+    }
+}
+
+/// The contents of an ebuild.
+pub struct EbuildText {
+    pub text: String,
+}
+
+/// Where the ebuild of a particular version was found.
+pub struct EbuildLocation {
+    pub version: String,
+    pub repository: String,
+    pub path: PathBuf,
+}
+
+impl EbuildLocation {
+    /// The `category/name` directory holding the ebuild.
+    pub fn package_path(&self) -> &Path {
+        self.path.parent().unwrap_or(&self.path)
+    }
+
+    /// The root of the repository holding the ebuild.
+    pub fn repo_path(&self) -> &Path {
+        let package_path = self.package_path();
+        package_path.parent().and_then(Path::parent).unwrap_or(package_path)
+    }
+}
+
+/// What the Summary page shows about a version of a package.
+pub struct Summary {
+    pub category: String,
+    pub name: String,
+    pub version: String,
+    pub repository: String,
+    pub description: String,
+    pub homepages: Vec<String>,
+    pub license: String,
+    pub slot: String,
+    pub keywords: String,
+    pub metadata: metadata::Metadata,
+}
+
+/// User-defined sets, one file per set, below the configuration root.
+const SETS_PATH: &str = "etc/portage/sets";
+
+/// The dependency classes shown on the Dependencies page, with what they are needed for.
+const DEPENDENCY_CLASSES: &[(&str, &str)] = &[("DEPEND", "build"), ("BDEPEND", "build host"), ("RDEPEND", "runtime"),
+                                              ("PDEPEND", "post-merge"), ("IDEPEND", "install time")];
+
+/// Whether an atom, or enough of a group of atoms, is satisfied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DependencyStatus {
+    Missing,
+    Available,
+    Installed,
+}
+
+impl DependencyStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DependencyStatus::Missing => "Missing",
+            DependencyStatus::Available => "Available",
+            DependencyStatus::Installed => "Installed",
+        }
+    }
+}
+
+/// A row of the Dependencies page: a dependency class, a group or an atom.
+pub struct DependencyNode {
+    pub label: String,
+    /// `None` for USE conditionals and dependency classes, whose status depends on the configuration.
+    pub status: Option<DependencyStatus>,
+    pub children: Vec<DependencyNode>,
+}
+
+/// An installed package depending on another, as recorded in its `DEPEND` or `RDEPEND`.
+pub struct ReverseDependency {
+    pub category: String,
+    pub name: String,
+    pub version: String,
+    pub atom: String,
+    /// `DEPEND` or `RDEPEND`.
+    pub kind: String,
+    /// Whether the atom is one of the alternatives of an any-of group, which another package may satisfy instead.
+    pub any_of: bool,
+}
+
+/// A row of the USE flags page.
+pub struct UseFlagRow {
+    pub flag: String,
+    /// The `+` or `-` default given in IUSE.
+    pub default: Option<bool>,
+    /// Whether merging the version would enable the flag, given the profile, `make.conf` and `package.use`.
+    pub enabled: bool,
+    /// Whether the installed build had the flag enabled, or `None` when the package is not installed or its build did
+    /// not have the flag.
+    pub installed: Option<bool>,
+    pub description: String,
+}
+
+impl UseFlagRow {
+    /// Whether merging the version would change the flag from the installed build.
+    pub fn changed(&self) -> bool {
+        self.installed.map_or(false, |installed| installed != self.enabled)
+    }
+}
+
+/// How the repositories changed since the database was last loaded.
+pub enum RepoChanges {
+    None,
+    /// Only the listed `(category, name)` packages need to be scanned again. The set may be empty when only files
+    /// outside of package directories changed, such as eclasses. A changed `profiles/package.mask` adds every package
+    /// named by its previous or current entries, as their recommended versions may change.
+    Packages(HashSet<(String, String)>),
+    /// The change cannot be narrowed down, for example because a repository is not a git checkout.
+    Everything,
+}
 
 pub trait PortixConnection {
-    fn parse_for_pkgs(&self);
-    fn parse_for_sets(&self);
-    fn parse_for_ebuilds(&self);
-    fn get_ebuild_with_query(&self, query: &str) -> String;
-    fn get_search_count(&self, search: &str) -> i32;
-    fn store_repo_hashes(&self);
-    fn tables_need_reloading(&self) -> bool;
-    fn tables_exist(&self) -> bool;
+    fn parse_for_pkgs(&self, repos: &[Repository]);
+    fn reindex_packages(&self, repos: &[Repository], packages: &HashSet<(String, String)>);
+    fn parse_for_installed<P: AsRef<Path>>(&self, root: P);
+    fn parse_for_sets<P: AsRef<Path>>(&self, config_root: P);
+    fn parse_for_upgrades(&self);
+    fn parse_for_masks(&self, repos: &[Repository]);
+    fn parse_for_deprecated(&self);
+    fn category_counts(&self, view: &View, repository: Option<&str>) -> Result<Vec<CategoryCount>, Error>;
+    fn packages_in_category(&self, view: &View, repository: Option<&str>, category: &str) -> Result<Vec<PackageRow>, Error>;
+    fn packages_in_set(&self, set: &str, repository: Option<&str>) -> Result<Vec<PackageRow>, Error>;
+    fn find_ebuild(&self, category: &str, name: &str, version: &str, repository: &str) -> Result<EbuildLocation, Error>;
+    fn get_ebuild(&self, category: &str, name: &str, version: &str, repository: &str) -> Result<EbuildText, Error>;
+    fn get_summary(&self, category: &str, name: &str, version: &str, repository: &str) -> Result<Summary, Error>;
+    fn get_dependencies(&self, category: &str, name: &str, version: &str, repository: &str) -> Result<Vec<DependencyNode>, Error>;
+    fn get_use_flags(&self, repos: &[Repository], use_config: &UseConfig, category: &str, name: &str, version: &str, repository: &str) -> Result<Vec<UseFlagRow>, Error>;
+    fn reverse_dependencies(&self, category: &str, name: &str) -> Result<Vec<ReverseDependency>, Error>;
+    fn installed_files(&self, category: &str, name: &str, version: &str) -> Result<Vec<ContentsEntry>, Error>;
+    fn versions_of(&self, category: &str, name: &str) -> Result<Vec<VersionRow>, Error>;
+    fn get_packages_matching(&self, atom: &Atom, repository: Option<&str>) -> Result<Vec<PackageRow>, Error>;
+    fn get_search_count(&self, search: &Search, repository: Option<&str>) -> Result<Vec<CategoryCount>, Error>;
+    fn search(&self, search: &Search, repository: Option<&str>, category: &str) -> Result<Vec<PackageRow>, Error>;
+    fn store_repo_hashes(&self, repos: &[Repository]);
+    fn repo_changes(&self, repos: &[Repository]) -> RepoChanges;
+    fn installed_changed<P: AsRef<Path>>(&self, root: P) -> bool;
 }
 
 impl PortixConnection for Connection {
-    fn parse_for_pkgs(&self) {
-        let child_output = thread::spawn(move || {
-            String::from_utf8(
-                Command::new("sh")
-                    .arg("-c")
-                    .arg(r#"NAMEVERSION='<category>=<name>=<version>=<description>\n' EIX_LIMIT_COMPACT=0 eix -c --format '<availableversions:NAMEVERSION>' --pure-packages|sed -e 's|[,\/"]||g' -e 's/=/,/;s/=/,/;s/=/,/'"#)
-                    .output()
-                    .expect("failed to get eix output")
-                    .stdout
-                ).expect("eix output is not UTF-8 compatible")
-        });
-
-        let child_installed_version_output = thread::spawn(move || {
-            String::from_utf8(
-                Command::new("sh")
-                    .arg("-c")
-                    .arg(r#"portageq match / '*/*'|sed -re "s/-([0-9])/,\1/" -e "s/\//\,/g""#)
-                    .output()
-                    .expect("failed to get qlist output")
-                    .stdout
-                ).expect("qlist output is not UTF-8 compatible")
-        });
-
-        let child_recommended_version_output = thread::spawn(move || {
-            String::from_utf8(
-                Command::new("sh")
-                    .arg("-c")
-                    .arg(r#"NAMEVERSION='<category>=<name>=<version>\n' EIX_LIMIT_COMPACT=0 eix -c --format '<bestversion:NAMEVERSION>' --pure-packages|sed -e 's|[,\/"]||g' -e 's/=/,/;s/=/,/'"#)
-                    .output()
-                    .expect("failed to get eix output")
-                    .stdout
-                ).expect("eix output is not UTF-8 compatible")
-        });
-
-        //let child_global_keywords = thread::spawn(move || {
-        //    let global_keywords = String::from_utf8(
-        //        Command::new("sh")
-        //            .arg("-c")
-        //            .arg(r"emerge --info|grep ACCEPT_KEYWORDS")
-        //            .output()
-        //            .expect("failed to get emerge output")
-        //            .stdout
-        //        ).expect("emerge output is not UTF-8 compatible");
-        //    global_keywords[(global_keywords.find("\"").unwrap() + 1)..global_keywords.rfind("\"").unwrap()]
-        //        .split(' ').map(|s| s.to_string()).collect::<Vec<_>>()
-        //});
-
-
-        //let child_arch_list = thread::spawn(move || {
-        //    let arch_list = String::from_utf8(
-        //        Command::new("sh")
-        //            .arg("-c")
-        //            .arg(r"cat $(portageq get_repo_path / gentoo)/profiles/arch.list")
-        //            .output()
-        //            .expect("failed to get portageq output")
-        //            .stdout
-        //        ).expect("portageq output is not UTF-8 compatible");
-        //    let mut list = Vec::new();
-        //    for arch in arch_list.lines() {
-        //        if arch.is_empty() {
-        //            break;
-        //        }
-        //        list.push(arch.to_string());
-        //    }
-        //    list
-        //});
-
-
-        let output = child_output.join().unwrap();
-        let installed_packages_output = child_installed_version_output.join().unwrap();
-        let recommended_packages_output = child_recommended_version_output.join().unwrap();
-
-        let mut all_packages_csv = fs::OpenOptions::new().write(true).create(true).open("./target/debug/portix_all_packages.csv").expect("failed to create portix_all_packages.csv file");
-        let mut installed_packages_csv = fs::OpenOptions::new().write(true).create(true).open("./target/debug/portix_installed_packages.csv").expect("failed to create portix_installed_packages.csv file");
-        let mut recommended_packages_csv = fs::OpenOptions::new().write(true).create(true).open("./target/debug/portix_recommended_packages.csv").expect("failed to create portix_recommended_packages.csv file");
-        all_packages_csv.write_all(output.as_bytes()).expect("failed to write all packages output into file");
-        installed_packages_csv.write_all(installed_packages_output.as_bytes()).expect("failed to write installed packages output into file");
-        recommended_packages_csv.write_all(recommended_packages_output.as_bytes()).expect("failed to write recommended packages output into file");
-
-        self.execute_batch("DROP TABLE IF EXISTS all_packages;
-                            CREATE VIRTUAL TABLE all_packages_vtab
-                            USING csv('./target/debug/portix_all_packages.csv', category, name, version, description);
-                            CREATE TABLE all_packages AS SELECT * FROM all_packages_vtab;
-                            DROP TABLE all_packages_vtab;
-
-                            DROP TABLE IF EXISTS installed_packages;
-                            CREATE VIRTUAL TABLE installed_packages_vtab
-                            USING csv('./target/debug/portix_installed_packages.csv', category, name, version);
-                            CREATE TABLE installed_packages AS SELECT * FROM installed_packages_vtab;
-                            DROP TABLE installed_packages_vtab;
-
-                            DROP TABLE IF EXISTS recommended_packages;
-                            CREATE VIRTUAL TABLE recommended_packages_vtab
-                            USING csv('./target/debug/portix_recommended_packages.csv', category, name, version);
-                            CREATE TABLE recommended_packages AS SELECT * FROM recommended_packages_vtab;
-                            DROP TABLE recommended_packages_vtab;").unwrap();
-
-        fs::remove_file("./target/debug/portix_all_packages.csv")
-            .expect("failed to remove portix_all_packages.csv file due to lack of permissions");
-        fs::remove_file("./target/debug/portix_installed_packages.csv")
-            .expect("failed to remove portix_installed_packages.csv file due to lack of permissions");
-        fs::remove_file("./target/debug/portix_recommended_packages.csv")
-            .expect("failed to remove portix_recommended_packages.csv file due to lack of permissions");
-
-        //let global_keywords = child_global_keywords.join().unwrap();
-        //let arch_list = child_arch_list.join().unwrap();
-        //let mut item = String::new();
-        //let mut desc = String::new();
-        //let mut versions = String::new();
-        //output.push_str("extra line needed to get previous item in iterator\n");
-        //for line in output.lines().map(|line| line.to_string()) {
-        //    let current_item = &line[0..line.find(' ').unwrap()];
-        //    if current_item == item {
-        //        let version_with_desc = &line[(line.find(' ').unwrap() + 1)..line.len()];
-        //        let version = &version_with_desc[0..version_with_desc.find(' ').unwrap()];
-        //        versions.push_str(&format!("{}\n", version));
-        //    }
-        //    else {
-        //        if !versions.is_empty() {
-        //            let (category, pkg) = {
-        //                let mut item_split = item.split("/");
-        //                (item_split.next().unwrap(), item_split.next().unwrap())
-        //            };
-        //            let blank = String::new();
-        //            let installed_version = installed_version_output_map.get(&item).unwrap_or(&blank);
-        //            let mut keyword = String::new();
-        //            let recommended_version = recommended_version_output_map.get(&item).unwrap_or({
-        //                for global_keyword in global_keywords.iter() {
-        //                    for arch in arch_list.iter() {
-        //                        if global_keyword == arch {
-        //                            keyword = "Not available".to_string();
-        //                            break;
-        //                        }
-        //                        else if *global_keyword == format!("~{}", arch) {
-        //                            keyword = "Keyworded".to_string();
-        //                            break;
-        //                        }
-        //                    }
-        //                }
-        //                &keyword
-        //            });
-        //            self.execute("INSERT INTO all_packages (category, package, versions, installed_version, recommended_version, description)
-        //                          VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        //                          &[&category, &pkg, &versions, &*installed_version, &*recommended_version, &desc]).unwrap();
-        //              
-        //              
-        //            if !installed_version.is_empty() {
-        //                self.execute("INSERT INTO installed_packages (category, package, versions, installed_version, recommended_version, description)
-        //                              VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        //                              &[&category, &pkg, &versions, &*installed_version, &*recommended_version, &desc]).unwrap();
-        //            }
-        //        }
-        //        versions.clear();
-        //        item = line[0..line.find(' ').unwrap()].to_string();
-        //        let version_with_desc = &line[(line.find(' ').unwrap() + 1)..line.len()];
-        //        let version = &version_with_desc[0..version_with_desc.find(' ').unwrap()];
-        //        desc = version_with_desc[(version_with_desc.find(' ').unwrap() + 1)..version_with_desc.len()].to_string();
-        //        versions.push_str(&format!("{}\n", version));
-        //    }
-        //}
-    }
-
-    fn parse_for_sets(&self) {
-        self.execute_batch("DROP TABLE IF EXISTS portage_sets;
-                            CREATE TABLE portage_sets (
-                            portage_set       TEXT,
-                            category_and_name TEXT,
-                            category          TEXT,
-                            name              TEXT
-                            );").unwrap();
-        for set in fs::read_dir("/etc/portage/sets").expect("failed to find /etc/portage/sets directory") {
-            let set = set.expect("intermittent IO error");
-            use ::std::io::BufRead;
-            let set_file = ::std::io::BufReader::new(fs::File::open(set.path()).unwrap());
-            for line in set_file.lines() {
-                let mut line = line.unwrap();
-                let mut split = line.split('/');
-                let (category, package) = {
-                    (split.next().unwrap(), split.next().unwrap())
-                }; 
-                
-                let mut statement = self.prepare("SELECT category, name FROM all_packages").expect("sql cannot be converted to a C string");
-                let mut rows = statement.query(&[]).expect("failed to query database");
-                while let Some(Ok(row)) = rows.next() {
-                    if row.get::<_, String>(0) == category && row.get::<_, String>(1) == package {
-                        self.execute("INSERT INTO portage_sets (portage_set, category_and_name, category, name)
-                                      VALUES (?1, ?2, ?3, ?4)",
-                                      &[&set.path().file_name().unwrap().to_str(), &line, &category, &package]).unwrap();
-                        break;
+    fn parse_for_pkgs(&self, repos: &[Repository]) {
+        // Clearing packages cascades to every table that refers to the available versions.
+        self.execute_batch("BEGIN;
+                            DELETE FROM packages;
+                            DELETE FROM packages_fts;").unwrap();
+        insert_ebuilds(self, &get_accept_keywords(), repos.iter().flat_map(|repo| scanner::scan_repository(&repo.location, &repo.name)));
+        self.execute_batch("COMMIT").unwrap();
+    }
+
+    fn reindex_packages(&self, repos: &[Repository], packages: &HashSet<(String, String)>) {
+        self.execute_batch("BEGIN").unwrap();
+        {
+            let mut delete_package = self.prepare("DELETE FROM packages WHERE category = ?1 AND name = ?2").expect("sql cannot be converted to a C string");
+            let mut delete_index = self.prepare("DELETE FROM packages_fts WHERE category = ?1 AND name = ?2").expect("sql cannot be converted to a C string");
+            for (category, name) in packages {
+                delete_package.execute(&[category, name]).expect("failed to delete data from packages table");
+                delete_index.execute(&[category, name]).expect("failed to delete data from packages_fts table");
+            }
+        }
+        // Every repository is rescanned for a changed package, as the recommended version may come from any of them.
+        insert_ebuilds(self, &get_accept_keywords(), packages.iter().flat_map(|(category, name)| {
+            repos.iter().flat_map(move |repo| scanner::scan_package(&repo.location, &repo.name, category, name))
+        }));
+        self.execute_batch("COMMIT").unwrap();
+    }
+
+    fn parse_for_installed<P: AsRef<Path>>(&self, root: P) {
+        // Taken before reading, so that a merge finishing halfway through is noticed on the next check.
+        let fingerprint = vdb::fingerprint(&root);
+        self.execute_batch("BEGIN;
+                            DELETE FROM installed_packages;").unwrap();
+        {
+            self.execute("INSERT OR REPLACE INTO vdb_fingerprints (root, fingerprint)
+                          VALUES (?1, ?2)",
+                          &[&root.as_ref().to_string_lossy().into_owned(), &fingerprint]).expect("failed to insert data into vdb_fingerprints table");
+
+            let mut statement = self.prepare("INSERT INTO installed_packages (category, name, version, slot, use_flags, iuse, keywords, repository, build_time, size, depend, rdepend, vdb_path)
+                                              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)").expect("sql cannot be converted to a C string");
+            let mut reverse_statement = self.prepare("INSERT OR IGNORE INTO reverse_dependencies (category, name, atom, any_of, kind, dependent_category, dependent_name, dependent_version)
+                                                      VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)").expect("sql cannot be converted to a C string");
+            for package in vdb::read_installed_packages(root) {
+                statement.execute(params![&package.category, &package.name, &package.version.as_str(), &package.slot, &package.use_flags,
+                                          &package.iuse, &package.keywords, &package.repository, &package.build_time, &package.size,
+                                          &package.depend, &package.rdepend, &package.path.to_str()])
+                         .expect("failed to insert data into installed_packages table");
+
+                // The dependencies recorded in the VDB already have their USE conditionals resolved.
+                for &(kind, dependencies) in &[("DEPEND", &package.depend), ("RDEPEND", &package.rdepend)] {
+                    let specs = match depend::parse(dependencies) {
+                        Ok(specs) => specs,
+                        Err(why) => {
+                            eprintln!("skipping {} of {}/{}-{}: {}", kind, package.category, package.name, package.version.as_str(), why);
+                            continue;
+                        }
+                    };
+                    for (text, atom, any_of) in depend::atoms(&specs) {
+                        if atom.blocker.is_some() {
+                            continue;
+                        }
+                        reverse_statement.execute(params![&atom.category, &atom.name, &text, &any_of, &kind, &package.category, &package.name,
+                                                          &package.version.as_str()])
+                                         .expect("failed to insert data into reverse_dependencies table");
                     }
                 }
             }
         }
+        self.execute_batch("COMMIT").unwrap();
     }
 
-    fn parse_for_ebuilds(&self) {
-        let repos = String::from_utf8(Command::new("sh")
-                .arg("-c")
-                .arg("portageq get_repos /")
-                .output()
-                .expect("failed to get repos list")
-                .stdout
-            ).expect("repo names are not UTF-8 compatible");
-        let repos: Vec<String> = repos.trim().split(' ').map(|repo| repo.to_owned()).collect();
-        let csv_string = Arc::new(Mutex::new(String::new()));
-
-        for repo in repos.into_iter() {
-            let repo_path = String::from_utf8(Command::new("sh")
-                    .arg("-c")
-                    .arg(format!("portageq get_repo_path / {}", repo))
-                    .output()
-                    .expect("failed to find repo path")
-                    .stdout
-                ).expect("repo path is not UTF-8 compatible");
-            let repo_path = repo_path.trim();
-
-            let csv_string_clone = csv_string.clone();
-            fs::read_dir(repo_path).expect("repo path does not exist").collect::<Vec<_>>().par_iter().for_each(|category_entry| {
-                let category_path = category_entry.as_ref().expect("intermittent IO error").path();
-                let category = category_path.clone();
-                let category = category.file_name().unwrap().to_string_lossy();
-                if category_path.is_file() || category.starts_with(".") {
-                    return;
+    fn parse_for_sets<P: AsRef<Path>>(&self, config_root: P) {
+        self.execute_batch("DELETE FROM portage_sets").unwrap();
+        // Without a sets directory there are simply no user sets.
+        for path in repos::conf_files(&config_root.as_ref().join(SETS_PATH)) {
+            let set_name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+            let mut text = String::new();
+            if let Err(why) = fs::File::open(&path).and_then(|mut file| file.read_to_string(&mut text)) {
+                eprintln!("failed to read set {}: {}", path.display(), why);
+                continue;
+            }
+            for line in text.lines() {
+                let line = line.split('#').next().unwrap_or("").trim();
+                if line.is_empty() || line.starts_with('@') {
+                    continue;
                 }
 
-                for package_entry in fs::read_dir(category_path).expect("category path does not exist") {
-                    let package_path = package_entry.expect("intermittent IO error").path();
-                    let package = package_path.clone();
-                    let package = package.file_name().unwrap().to_string_lossy();
-                    if package_path.is_file() || package.starts_with(".") {
+                let atom = match line.parse::<Atom>() {
+                    Ok(atom) => atom,
+                    Err(why) => {
+                        eprintln!("skipping entry in set {}: {}", set_name, why);
                         continue;
                     }
+                };
+                if atom.blocker.is_some() {
+                    continue;
+                }
 
-                    for file_entry in fs::read_dir(package_path).unwrap() {
-                        let file_path = file_entry.expect("intermittent IO error").path();
-                        let file_string = file_path.file_name().unwrap().to_string_lossy();
-                        if file_string.ends_with(".ebuild") {
-                            let regex = Regex::new(r".*-(\d.*).ebuild").unwrap();
-                            let version = &regex.captures(&file_string).unwrap()[1];
-                            let mut lock = csv_string_clone.lock().expect("failed to lock csv string");
-                            lock.push_str(&category);
-                            lock.push_str(",");
-                            lock.push_str(&package);
-                            lock.push_str(",");
-                            lock.push_str(version);
-                            lock.push_str(",");
-                            lock.push_str(&file_path.to_str().unwrap());
-                            lock.push_str("\n");
-                        }
+                let mut statement = self.prepare("SELECT version, slot, repository FROM all_packages WHERE category = ?1 AND name = ?2").expect("sql cannot be converted to a C string");
+                let mut rows = statement.query(&[&atom.category, &atom.name]).expect("failed to query database");
+                while let Some(row) = rows.next().expect("failed to query database") {
+                    let version = match row.get_unwrap::<_, String>(0).parse::<Version>() {
+                        Ok(version) => version,
+                        Err(_) => continue,
+                    };
+                    if atom.matches(&atom.category, &atom.name, &version, &row.get_unwrap::<_, String>(1), &row.get_unwrap::<_, String>(2)) {
+                        self.execute("INSERT OR IGNORE INTO portage_sets (portage_set, atom, category_and_name, category, name)
+                                      VALUES (?1, ?2, ?3, ?4, ?5)",
+                                      params![&set_name, &line, &format!("{}/{}", atom.category, atom.name), &atom.category, &atom.name]).unwrap();
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn parse_for_upgrades(&self) {
+        self.execute_batch("DELETE FROM upgradeable_packages").unwrap();
+
+        let accept_keywords = get_accept_keywords();
+        let mut best_per_slot: HashMap<(String, String, String), (Version, String)> = HashMap::new();
+        {
+            let mut statement = self.prepare("SELECT category, name, version, slot, keywords, repository FROM all_packages").expect("sql cannot be converted to a C string");
+            let mut rows = statement.query(NO_PARAMS).expect("failed to query database");
+            let mut masks: HashMap<(String, String), Vec<Atom>> = HashMap::new();
+            while let Some(row) = rows.next().expect("failed to query database") {
+                if !keywords_accepted(&row.get_unwrap::<_, String>(4), &accept_keywords) {
+                    continue;
+                }
+                let version = match row.get_unwrap::<_, String>(2).parse::<Version>() {
+                    Ok(version) => version,
+                    Err(_) => continue,
+                };
+                let (category, name, slot) = (row.get_unwrap::<_, String>(0), row.get_unwrap::<_, String>(1), row.get_unwrap::<_, String>(3));
+                let repository = row.get_unwrap::<_, String>(5);
+                let package_masks = masks.entry((category.clone(), name.clone())).or_insert_with(|| package_masks(self, &category, &name));
+                if package_masks.iter().any(|mask| mask.matches(&category, &name, &version, &slot, &repository)) {
+                    continue;
+                }
+                let key = (category, name, main_slot(&slot).to_owned());
+                let best = best_per_slot.entry(key).or_insert_with(|| (version.clone(), repository.clone()));
+                if version > best.0 {
+                    *best = (version, repository);
+                }
+            }
+        }
+
+        self.execute_batch("BEGIN").unwrap();
+        {
+            let mut statement = self.prepare("SELECT category, name, version, slot FROM installed_packages").expect("sql cannot be converted to a C string");
+            let mut rows = statement.query(NO_PARAMS).expect("failed to query database");
+            while let Some(row) = rows.next().expect("failed to query database") {
+                let (category, name, slot) = (row.get_unwrap::<_, String>(0), row.get_unwrap::<_, String>(1), row.get_unwrap::<_, String>(3));
+                let installed_version = row.get_unwrap::<_, String>(2);
+                let (best, repository) = match best_per_slot.get(&(category.clone(), name.clone(), main_slot(&slot).to_owned())) {
+                    Some(best) => best,
+                    None => continue,
+                };
+                match installed_version.parse::<Version>() {
+                    Ok(ref installed) if best > installed => {
+                        self.execute("INSERT INTO upgradeable_packages (category, name, slot, installed_version, version, repository)
+                                      VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                                      params![&category, &name, &slot, &installed_version, &best.as_str(), repository]).expect("failed to insert data into upgradeable_packages table");
                     }
+                    _ => (),
+                }
+            }
+        }
+        self.execute_batch("COMMIT").unwrap();
+    }
+
+    fn parse_for_masks(&self, repos: &[Repository]) {
+        self.execute_batch("BEGIN;
+                            DELETE FROM package_masks;").unwrap();
+        {
+            let mut statement = self.prepare("INSERT OR IGNORE INTO package_masks (repository, atom, category, name)
+                                              VALUES (?1, ?2, ?3, ?4)").expect("sql cannot be converted to a C string");
+            for repo in repos {
+                for (text, atom) in scanner::read_package_mask(&repo.location) {
+                    statement.execute(&[&repo.name, &text, &atom.category, &atom.name]).expect("failed to insert data into package_masks table");
+                }
+            }
+        }
+        self.execute_batch("COMMIT").unwrap();
+    }
+
+    fn parse_for_deprecated(&self) {
+        self.execute_batch("DELETE FROM deprecated_packages").unwrap();
+
+        self.execute_batch("BEGIN").unwrap();
+        {
+            let mut statement = self.prepare("SELECT category, name, version, slot, repository FROM installed_packages").expect("sql cannot be converted to a C string");
+            let mut rows = statement.query(NO_PARAMS).expect("failed to query database");
+            let mut available = self.prepare("SELECT version FROM ebuilds WHERE category = ?1 AND name = ?2").expect("sql cannot be converted to a C string");
+            while let Some(row) = rows.next().expect("failed to query database") {
+                let (category, name, installed_version) = (row.get_unwrap::<_, String>(0), row.get_unwrap::<_, String>(1), row.get_unwrap::<_, String>(2));
+                let (slot, repository) = (row.get_unwrap::<_, String>(3), row.get_unwrap::<_, String>(4));
+                let version = match installed_version.parse::<Version>() {
+                    Ok(version) => version,
+                    Err(_) => continue,
+                };
+
+                let versions: Vec<String> = available.query_map(&[&category, &name], |row| row.get(0))
+                    .expect("failed to query database")
+                    .filter_map(|version| version.ok())
+                    .collect();
+                let reason = if versions.is_empty() {
+                    "Removed from all repositories"
+                } else if !versions.iter().any(|available| available.parse::<Version>().ok().map_or(false, |available| available == version)) {
+                    "Installed version is no longer available"
+                } else if package_masks(self, &category, &name).iter().any(|mask| mask.matches(&category, &name, &version, &slot, &repository)) {
+                    "Masked in profiles/package.mask"
+                } else {
+                    continue;
+                };
+
+                self.execute("INSERT INTO deprecated_packages (category, name, version, reason)
+                              VALUES (?1, ?2, ?3, ?4)",
+                              params![&category, &name, &installed_version, &reason]).expect("failed to insert data into deprecated_packages table");
+            }
+        }
+        self.execute_batch("COMMIT").unwrap();
+    }
+
+    fn category_counts(&self, view: &View, repository: Option<&str>) -> Result<Vec<CategoryCount>, Error> {
+        let query = match *view {
+            View::All => "SELECT category, count(DISTINCT name) AS pkg_count
+                          FROM all_packages
+                          WHERE ?1 IS NULL OR repository = ?1
+                          GROUP BY category".to_owned(),
+            View::Installed => format!("SELECT category, count(DISTINCT name) AS pkg_count
+                                        FROM installed_packages
+                                        WHERE 1 {}
+                                        GROUP BY category", repository_filter("installed_packages", 1)),
+            View::Search(ref search) => return self.get_search_count(search, repository),
+            View::Upgradeable => "SELECT category, count(DISTINCT name) AS pkg_count
+                                  FROM upgradeable_packages
+                                  WHERE ?1 IS NULL OR repository = ?1
+                                  GROUP BY category".to_owned(),
+            View::Deprecated => format!("SELECT category, count(DISTINCT name) AS pkg_count
+                                         FROM deprecated_packages
+                                         WHERE 1 {}
+                                         GROUP BY category", repository_filter("deprecated_packages", 1)),
+            View::Sets => format!("SELECT portage_set, count(DISTINCT category_and_name) AS pkg_count
+                                   FROM portage_sets
+                                   WHERE 1 {}
+                                   GROUP BY portage_set", repository_filter("portage_sets", 1)),
+        };
+
+        let mut statement = self.prepare(&query)?;
+        let counts = statement.query_and_then(&[&repository], CategoryCount::from_row)?.collect::<Result<_, _>>()?;
+        Ok(counts)
+    }
+
+    fn packages_in_category(&self, view: &View, repository: Option<&str>, category: &str) -> Result<Vec<PackageRow>, Error> {
+        let query = match *view {
+            View::All => format!(r#"SELECT all_packages.category,
+                                    all_packages.name AS package_name,
+                                    IFNULL(installed_packages.version, "") AS installed_version,
+                                    IFNULL(recommended_packages.version, "Not available") AS recommended_version,
+                                    all_packages.description AS description,
+                                    IFNULL(recommended_packages.repository, "") AS repository
+                                    FROM all_packages
+                                    LEFT JOIN installed_packages
+                                    ON all_packages.category = installed_packages.category
+                                    AND all_packages.name = installed_packages.name
+                                    LEFT JOIN recommended_packages
+                                    ON all_packages.category = recommended_packages.category
+                                    AND all_packages.name = recommended_packages.name
+                                    WHERE all_packages.category = ?1 {}
+                                    GROUP BY package_name
+                                    ORDER BY package_name ASC"#, repository_filter("all_packages", 2)),
+            View::Installed => format!(r#"SELECT installed_packages.category,
+                                          installed_packages.name AS package_name,
+                                          IFNULL(installed_packages.version, "") AS installed_version,
+                                          IFNULL(recommended_packages.version, "Not available") AS recommended_version,
+                                          IFNULL(all_packages.description, "") AS description,
+                                          IFNULL(recommended_packages.repository, "") AS repository
+                                          FROM installed_packages
+                                          LEFT JOIN all_packages
+                                          ON installed_packages.category = all_packages.category
+                                          AND installed_packages.name = all_packages.name
+                                          LEFT JOIN recommended_packages
+                                          ON installed_packages.category = recommended_packages.category
+                                          AND installed_packages.name = recommended_packages.name
+                                          WHERE installed_packages.category = ?1 {}
+                                          GROUP BY package_name
+                                          ORDER BY package_name ASC"#, repository_filter("installed_packages", 2)),
+            View::Search(ref search) => return with_versions(self, self.search(search, repository, category)?),
+            View::Upgradeable => r#"SELECT upgradeable_packages.category,
+                                    upgradeable_packages.name AS package_name,
+                                    upgradeable_packages.installed_version AS installed_version,
+                                    upgradeable_packages.version AS recommended_version,
+                                    IFNULL(all_packages.description, "") AS description,
+                                    upgradeable_packages.repository AS repository
+                                    FROM upgradeable_packages
+                                    LEFT JOIN all_packages
+                                    ON upgradeable_packages.category = all_packages.category
+                                    AND upgradeable_packages.name = all_packages.name
+                                    AND upgradeable_packages.version = all_packages.version
+                                    AND upgradeable_packages.repository = all_packages.repository
+                                    WHERE upgradeable_packages.category = ?1
+                                    AND (?2 IS NULL OR upgradeable_packages.repository = ?2)
+                                    GROUP BY package_name, upgradeable_packages.slot
+                                    ORDER BY package_name ASC"#.to_owned(),
+            View::Deprecated => format!(r#"SELECT deprecated_packages.category,
+                                           deprecated_packages.name AS package_name,
+                                           deprecated_packages.version AS installed_version,
+                                           IFNULL(recommended_packages.version, "Not available") AS recommended_version,
+                                           deprecated_packages.reason AS description,
+                                           IFNULL(recommended_packages.repository, "") AS repository
+                                           FROM deprecated_packages
+                                           LEFT JOIN recommended_packages
+                                           ON deprecated_packages.category = recommended_packages.category
+                                           AND deprecated_packages.name = recommended_packages.name
+                                           WHERE deprecated_packages.category = ?1 {}
+                                           ORDER BY package_name ASC"#, repository_filter("deprecated_packages", 2)),
+            View::Sets => return with_versions(self, self.packages_in_set(category, repository)?),
+        };
+        with_versions(self, query_package_rows(self, &query, &[&category, &repository])?)
+    }
+
+    fn packages_in_set(&self, set: &str, repository: Option<&str>) -> Result<Vec<PackageRow>, Error> {
+        let query = format!(r#"SELECT portage_sets.category,
+                               portage_sets.name,
+                               IFNULL(installed_packages.version, "") AS installed_version,
+                               IFNULL(recommended_packages.version, "Not available") AS recommended_version,
+                               IFNULL(all_packages.description, "") AS description,
+                               IFNULL(recommended_packages.repository, "") AS repository
+                               FROM portage_sets
+                               LEFT JOIN all_packages
+                               ON portage_sets.category = all_packages.category
+                               AND portage_sets.name = all_packages.name
+                               LEFT JOIN installed_packages
+                               ON portage_sets.category = installed_packages.category
+                               AND portage_sets.name = installed_packages.name
+                               LEFT JOIN recommended_packages
+                               ON portage_sets.category = recommended_packages.category
+                               AND portage_sets.name = recommended_packages.name
+                               WHERE portage_sets.portage_set = ?1 {}
+                               GROUP BY portage_sets.category, portage_sets.name
+                               ORDER BY portage_sets.category ASC, portage_sets.name ASC"#, repository_filter("portage_sets", 2));
+        query_package_rows(self, &query, &[&set, &repository])
+    }
+
+    fn find_ebuild(&self, category: &str, name: &str, version: &str, repository: &str) -> Result<EbuildLocation, Error> {
+        // The exact version from the exact repository when it is still available, otherwise the newest one.
+        let mut statement = self.prepare("SELECT version, repository, ebuild_path FROM ebuilds
+                                          WHERE category = ?1 AND name = ?2
+                                          ORDER BY (version = ?3 AND repository = ?4) DESC, version COLLATE gentoo_version DESC
+                                          LIMIT 1")?;
+        let mut locations = statement.query_and_then(&[&category, &name, &version, &repository], |row| -> rusqlite::Result<EbuildLocation> {
+            Ok(EbuildLocation {
+                version: row.get(0)?,
+                repository: row.get(1)?,
+                path: PathBuf::from(row.get::<_, String>(2)?),
+            })
+        })?;
+        match locations.next() {
+            Some(location) => Ok(location?),
+            None => Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, format!("no ebuild found for {}/{}", category, name)))),
+        }
+    }
+
+    fn get_ebuild(&self, category: &str, name: &str, version: &str, repository: &str) -> Result<EbuildText, Error> {
+        let path = self.find_ebuild(category, name, version, repository)?.path;
+        let mut text = String::new();
+        fs::File::open(&path)?.read_to_string(&mut text)?;
+        Ok(EbuildText { text })
+    }
+
+    fn get_summary(&self, category: &str, name: &str, version: &str, repository: &str) -> Result<Summary, Error> {
+        let location = self.find_ebuild(category, name, version, repository)?;
+        let metadata = metadata::read_metadata(location.package_path()).unwrap_or_default();
+        let mut variables = scanner::read_variables(location.repo_path(), category, &format!("{}-{}", name, location.version), &location.path);
+        let mut take = |key: &str| variables.remove(key).unwrap_or_default();
+
+        Ok(Summary {
+            category: category.to_owned(),
+            name: name.to_owned(),
+            description: take("DESCRIPTION"),
+            homepages: take("HOMEPAGE").split_whitespace().map(|homepage| homepage.to_owned()).collect(),
+            license: take("LICENSE"),
+            slot: take("SLOT"),
+            keywords: take("KEYWORDS"),
+            metadata,
+            version: location.version,
+            repository: location.repository,
+        })
+    }
+
+    fn get_dependencies(&self, category: &str, name: &str, version: &str, repository: &str) -> Result<Vec<DependencyNode>, Error> {
+        let location = self.find_ebuild(category, name, version, repository)?;
+        let variables = scanner::read_variables(location.repo_path(), category, &format!("{}-{}", name, location.version), &location.path);
+
+        let mut classes = Vec::new();
+        for &(key, purpose) in DEPENDENCY_CLASSES {
+            let children = match variables.get(key).map(|text| depend::parse(text)) {
+                None => continue,
+                Some(Ok(ref specs)) if specs.is_empty() => continue,
+                Some(Ok(specs)) => specs.iter().map(|spec| dependency_node(self, spec)).collect(),
+                Some(Err(why)) => vec![DependencyNode { label: why.to_string(), status: None, children: Vec::new() }],
+            };
+            classes.push(DependencyNode { label: format!("{} ({})", key, purpose), status: None, children });
+        }
+        Ok(classes)
+    }
+
+    fn get_use_flags(&self, repos: &[Repository], use_config: &UseConfig, category: &str, name: &str, version: &str, repository: &str) -> Result<Vec<UseFlagRow>, Error> {
+        let location = self.find_ebuild(category, name, version, repository)?;
+        let variables = scanner::read_variables(location.repo_path(), category, &format!("{}-{}", name, location.version), &location.path);
+        let package_flags = match location.version.parse::<Version>() {
+            Ok(parsed) => use_config.package_flags(category, name, &parsed, variables.get("SLOT").map_or("0", |slot| &**slot), &location.repository),
+            Err(_) => Vec::new(),
+        };
+
+        // The installed build of the same version when there is one, otherwise any installed version.
+        let mut statement = self.prepare("SELECT use_flags, iuse FROM installed_packages
+                                          WHERE category = ?1 AND name = ?2
+                                          ORDER BY version = ?3 DESC
+                                          LIMIT 1")?;
+        let installed = statement.query_and_then(params![&category, &name, &location.version], |row| -> rusqlite::Result<(String, String)> {
+            Ok((row.get(0)?, row.get(1)?))
+        })?.next().map_or(Ok(None), |installed| installed.map(Some))?;
+        let installed_iuse: HashSet<&str> = installed.as_ref()
+            .map_or_else(HashSet::new, |(_, iuse)| iuse.split_whitespace().map(|flag| flag.trim_start_matches(|c| c == '+' || c == '-')).collect());
+        let installed_use: HashSet<&str> = installed.as_ref().map_or_else(HashSet::new, |(use_flags, _)| use_flags.split_whitespace().collect());
+
+        let mut flags: Vec<UseFlagRow> = variables.get("IUSE").map_or("", |iuse| &**iuse).split_whitespace()
+            .map(|entry| {
+                let (flag, default) = match entry.chars().next() {
+                    Some('+') => (&entry[1..], Some(true)),
+                    Some('-') => (&entry[1..], Some(false)),
+                    _ => (entry, None),
+                };
+                UseFlagRow {
+                    flag: flag.to_owned(),
+                    default,
+                    enabled: use_config.enabled(flag, default, &package_flags),
+                    installed: if installed_iuse.contains(flag) { Some(installed_use.contains(flag)) } else { None },
+                    description: String::new(),
                 }
-            });
+            })
+            .collect();
+        flags.sort_by(|a, b| a.flag.cmp(&b.flag));
+        flags.dedup_by(|a, b| a.flag == b.flag);
+
+        // Descriptions are looked up in metadata.xml first, then in the profiles of the repository holding the ebuild,
+        // and then in those of the other repositories, which is where overlays find the global flags.
+        let mut descriptions: HashMap<String, String> = metadata::read_metadata(location.package_path())
+            .map_or_else(HashMap::new, |metadata| metadata.use_flags.into_iter().collect());
+        let repo_paths = Some(location.repo_path()).into_iter()
+            .chain(repos.iter().map(|repo| &*repo.location).filter(|path| *path != location.repo_path()));
+        for repo_path in repo_paths {
+            if flags.iter().all(|row| descriptions.contains_key(&row.flag)) {
+                break;
+            }
+            for (flag, description) in scanner::read_local_use_descriptions(repo_path, category, name).into_iter()
+                .chain(scanner::read_use_descriptions(repo_path)) {
+                descriptions.entry(flag).or_insert(description);
+            }
+        }
+        for row in &mut flags {
+            if let Some(description) = descriptions.remove(&row.flag) {
+                row.description = description;
+            }
         }
+        Ok(flags)
+    }
 
-        csv_string.try_lock().unwrap().pop(); // pop out the last unneeded new line character
-        let mut ebuilds_csv = fs::File::create("./target/debug/portix_ebuilds.csv").expect("failed to create portix_ebuilds.csv file");
-        ebuilds_csv.write_all(&mut csv_string.try_lock().unwrap().as_bytes()).expect("failed to write to portix_ebuilds.csv file");
+    fn reverse_dependencies(&self, category: &str, name: &str) -> Result<Vec<ReverseDependency>, Error> {
+        let mut statement = self.prepare("SELECT version, slot, repository FROM installed_packages WHERE category = ?1 AND name = ?2")?;
+        let installed: Vec<(Version, String, String)> = statement.query_and_then(&[&category, &name], |row| -> rusqlite::Result<(String, String, String)> {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?.collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|(version, slot, repository)| version.parse().ok().map(|version| (version, slot, repository)))
+            .collect();
 
-        self.execute_batch("DROP TABLE IF EXISTS ebuilds;
-                            CREATE VIRTUAL TABLE ebuilds_vtab
-                            USING csv('./target/debug/portix_ebuilds.csv', category, name, version, ebuild_path);
-                            CREATE TABLE ebuilds AS SELECT * FROM ebuilds_vtab;
-                            DROP TABLE ebuilds_vtab;").unwrap();
+        // Atoms naming the package are only kept if one of its installed versions satisfies them.
+        let mut statement = self.prepare("SELECT dependent_category, dependent_name, dependent_version, atom, kind, any_of
+                                          FROM reverse_dependencies
+                                          WHERE category = ?1 AND name = ?2
+                                          ORDER BY dependent_category, dependent_name, dependent_version COLLATE gentoo_version, kind")?;
+        let dependents = statement.query_and_then(&[&category, &name], |row| -> rusqlite::Result<ReverseDependency> {
+            Ok(ReverseDependency {
+                category: row.get(0)?,
+                name: row.get(1)?,
+                version: row.get(2)?,
+                atom: row.get(3)?,
+                kind: row.get(4)?,
+                any_of: row.get(5)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+        Ok(dependents.into_iter()
+            .filter(|dependent| match dependent.atom.parse::<Atom>() {
+                Ok(atom) => installed.iter().any(|(version, slot, repository)| atom.matches(category, name, version, slot, repository)),
+                Err(_) => false,
+            })
+            .collect())
+    }
 
-        fs::remove_file("./target/debug/portix_ebuilds.csv")
-            .expect("failed to remove portix_ebuilds.csv file due to lack of permissions");
+    fn installed_files(&self, category: &str, name: &str, version: &str) -> Result<Vec<ContentsEntry>, Error> {
+        // The installed build of the same version when there is one, otherwise any installed version.
+        let mut statement = self.prepare("SELECT vdb_path FROM installed_packages
+                                          WHERE category = ?1 AND name = ?2
+                                          ORDER BY version = ?3 DESC, version COLLATE gentoo_version DESC
+                                          LIMIT 1")?;
+        let vdb_path = match statement.query_and_then(&[&category, &name, &version], |row| row.get::<_, String>(0))?.next() {
+            Some(vdb_path) => vdb_path?,
+            None => return Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, format!("{}/{} is not installed", category, name)))),
+        };
+        Ok(vdb::read_contents(Path::new(&vdb_path))?)
     }
 
-    fn get_ebuild_with_query(&self, query: &str) -> String {
-        let mut statement = self.prepare(query).expect("sql cannot be converted to a C string");
-        let mut queries = statement.query(&[]).expect("failed to query database");
-        if let Some(Ok(query)) = queries.next() {
-            let mut ebuild_text = String::new();
-            let mut ebuild_file = fs::File::open(query.get::<_, String>(0)).unwrap();
-            ebuild_file.read_to_string(&mut ebuild_text).unwrap();
-            ebuild_text
+    fn versions_of(&self, category: &str, name: &str) -> Result<Vec<VersionRow>, Error> {
+        let mut statement = self.prepare("SELECT all_packages.version, all_packages.slot, all_packages.repository, all_packages.arch_keyword,
+                                          all_packages.accepted, installed_packages.version IS NOT NULL
+                                          FROM all_packages
+                                          LEFT JOIN installed_packages
+                                          ON all_packages.category = installed_packages.category
+                                          AND all_packages.name = installed_packages.name
+                                          AND all_packages.version = installed_packages.version
+                                          WHERE all_packages.category = ?1
+                                          AND all_packages.name = ?2
+                                          ORDER BY all_packages.version COLLATE gentoo_version DESC, all_packages.repository ASC")?;
+        let mut versions: Vec<VersionRow> = statement.query_and_then(&[&category, &name], |row| -> rusqlite::Result<VersionRow> {
+            Ok(VersionRow {
+                version: row.get(0)?,
+                slot: row.get(1)?,
+                repository: row.get(2)?,
+                arch_keyword: row.get(3)?,
+                accepted: row.get(4)?,
+                masked: false,
+                installed: row.get(5)?,
+            })
+        })?.collect::<Result<_, _>>()?;
+
+        let masks = package_masks(self, category, name);
+        for row in &mut versions {
+            if let Ok(version) = row.version.parse::<Version>() {
+                row.masked = masks.iter().any(|mask| mask.matches(category, name, &version, &row.slot, &row.repository));
+            }
+        }
+        Ok(versions)
+    }
+
+    fn get_packages_matching(&self, atom: &Atom, repository: Option<&str>) -> Result<Vec<PackageRow>, Error> {
+        let mut statement = self.prepare(r#"SELECT all_packages.category,
+                                            all_packages.name AS package_name,
+                                            IFNULL(installed_packages.version, "") AS installed_version,
+                                            IFNULL(recommended_packages.version, "Not available") AS recommended_version,
+                                            all_packages.description AS description,
+                                            IFNULL(recommended_packages.repository, "") AS repository,
+                                            all_packages.version,
+                                            all_packages.slot,
+                                            all_packages.repository
+                                            FROM all_packages
+                                            LEFT JOIN installed_packages
+                                            ON all_packages.category = installed_packages.category
+                                            AND all_packages.name = installed_packages.name
+                                            LEFT JOIN recommended_packages
+                                            ON all_packages.category = recommended_packages.category
+                                            AND all_packages.name = recommended_packages.name
+                                            WHERE all_packages.category = ?1
+                                            AND all_packages.name = ?2
+                                            AND (?3 IS NULL OR all_packages.repository = ?3)"#)?;
+        let mut rows = statement.query(params![&atom.category, &atom.name, &repository])?;
+
+        let mut packages: Vec<PackageRow> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let version = match row.get::<_, String>(6)?.parse::<Version>() {
+                Ok(version) => version,
+                Err(_) => continue,
+            };
+            let package = PackageRow::from_row(row)?;
+            if atom.matches(&atom.category, &package.name, &version, &row.get::<_, String>(7)?, &row.get::<_, String>(8)?)
+                && !packages.iter().any(|matched| matched.name == package.name) {
+                packages.push(package);
+            }
         }
-        else {
-            String::new()
+        Ok(packages)
+    }
+
+    fn get_search_count(&self, search: &Search, repository: Option<&str>) -> Result<Vec<CategoryCount>, Error> {
+        if let Ok(atom) = search.text.parse::<Atom>() {
+            let count = self.get_packages_matching(&atom, repository)?.iter()
+                .filter(|package| !search.installed_only || !package.installed_version.is_empty())
+                .count() as i32;
+            return Ok(if count == 0 { Vec::new() } else { vec![CategoryCount { category: atom.category, count }] });
         }
+
+        let count = format!(r#"SELECT hits.category, count(DISTINCT hits.name) as search_count
+                               FROM (
+                               SELECT category, name
+                               FROM packages_fts
+                               WHERE packages_fts MATCH ?1
+                               ) AS hits
+                               LEFT JOIN installed_packages
+                               ON hits.category = installed_packages.category
+                               AND hits.name = installed_packages.name
+                               WHERE 1 {} {}
+                               GROUP BY hits.category
+                               ORDER BY hits.category ASC"#,
+                               search.installed_filter(), repository_filter("hits", 2));
+        let mut statement = self.prepare(&count)?;
+        let counts = statement.query_and_then(params![&search.fts_query(), &repository], CategoryCount::from_row)?.collect::<Result<_, _>>()?;
+        Ok(counts)
     }
 
-    fn get_search_count(&self, search: &str) -> i32 {
-        let count = format!(r#"SELECT count() as search_count
+    fn search(&self, search: &Search, repository: Option<&str>, category: &str) -> Result<Vec<PackageRow>, Error> {
+        if let Ok(atom) = search.text.parse::<Atom>() {
+            if atom.category != category {
+                return Ok(Vec::new());
+            }
+            return Ok(self.get_packages_matching(&atom, repository)?.into_iter()
+                .filter(|package| !search.installed_only || !package.installed_version.is_empty())
+                .collect());
+        }
+
+        let query = format!(r#"SELECT hits.category,
+                               hits.name AS package_name,
+                               IFNULL(installed_packages.version, "") AS installed_version,
+                               IFNULL(recommended_packages.version, "Not available") AS recommended_version,
+                               hits.description AS description,
+                               IFNULL(recommended_packages.repository, "") AS repository
                                FROM (
-                               SELECT *
-                               FROM all_packages
-                               WHERE all_packages.name LIKE '%{}%'
-                               GROUP BY all_packages.name
-                               ORDER BY all_packages.category ASC
-                               )"#,
-                               search);
-        let mut statement = self.prepare(&count).expect("sql cannot be converted to a C string");
-        let mut query_count = statement.query(&[]).expect("failed to query database");
-        query_count.next().unwrap().unwrap().get::<_, i32>(0)
-    }
-
-    fn store_repo_hashes(&self) {
-        self.execute_batch("DROP TABLE IF EXISTS repo_hashes;
-                            CREATE TABLE repo_hashes (
-                            repo_path  TEXT,
-                            head_hash TEXT
-                            );").unwrap();
-
-        let repos = String::from_utf8(Command::new("sh")
-                .arg("-c")
-                .arg("portageq get_repos /")
-                .output()
-                .expect("failed to get repos list")
-                .stdout
-            ).expect("repo names are not UTF-8 compatible");
-        let repos: Vec<_> = repos.trim().split(' ').collect();
-
-        for repo in repos.into_iter() {
-            let repo_path = String::from_utf8(Command::new("sh")
-                    .arg("-c")
-                    .arg(format!("portageq get_repo_path / {}", repo))
-                    .output()
-                    .expect("failed to find repo path")
-                    .stdout
-                ).expect("repo path is not UTF-8 compatible");
-            let repo_path = repo_path.trim();
-            let git_head_hash = String::from_utf8(
-                    match Command::new("sh").arg("-c").arg("git rev-parse @").current_dir(repo_path).output() {
-                        Ok(a) => a.stdout,
-                        Err(_) => continue,
-                    }
-                ).expect("repo hash is not UTF-8 compatible");
+                               SELECT category, name, description, rank
+                               FROM packages_fts
+                               WHERE packages_fts MATCH ?1
+                               AND category = ?2
+                               ) AS hits
+                               LEFT JOIN installed_packages
+                               ON hits.category = installed_packages.category
+                               AND hits.name = installed_packages.name
+                               LEFT JOIN recommended_packages
+                               ON hits.category = recommended_packages.category
+                               AND hits.name = recommended_packages.name
+                               WHERE 1 {} {}
+                               GROUP BY package_name
+                               ORDER BY hits.rank"#,
+                               search.installed_filter(), repository_filter("hits", 3));
+        query_package_rows(self, &query, &[&search.fts_query(), &category, &repository])
+    }
 
-            self.execute("INSERT INTO repo_hashes (repo_path, head_hash)
-                          VALUES (?1, ?2)",
-                          &[&repo_path, &git_head_hash.trim()]).expect("failed to insert data into repo_hashes table");
+    fn store_repo_hashes(&self, repos: &[Repository]) {
+        self.execute_batch("DELETE FROM repo_hashes").unwrap();
+
+        for repo in repos {
+            let (kind, fingerprint) = repo_fingerprint(&repo.location);
+            self.execute("INSERT INTO repo_hashes (repo_path, head_hash, fingerprint_kind)
+                          VALUES (?1, ?2, ?3)",
+                          params![&repo.location.to_string_lossy().into_owned(), &fingerprint, &kind]).expect("failed to insert data into repo_hashes table");
         }
     }
 
-    fn tables_need_reloading(&self) -> bool {
-        let mut statement = self.prepare("SELECT repo_path, head_hash FROM repo_hashes").expect("sql cannot be converted to a C string");
-        let mut rows = statement.query(&[]).expect("failed to query database");
+    fn repo_changes(&self, repos: &[Repository]) -> RepoChanges {
+        let mut statement = self.prepare("SELECT repo_path, head_hash, fingerprint_kind FROM repo_hashes").expect("sql cannot be converted to a C string");
+        let mut rows = statement.query(NO_PARAMS).expect("failed to query database");
 
-        while let Some(Ok(row)) = rows.next() {
-            let repo_path = row.get::<_, String>(0);
-            let previous_head_hash = row.get::<_, String>(1);
+        let mut stored = HashMap::new();
+        while let Some(row) = rows.next().expect("failed to query database") {
+            stored.insert(row.get_unwrap::<_, String>(0), (row.get_unwrap::<_, String>(2), row.get_unwrap::<_, String>(1)));
+        }
 
-            let new_head_hash = String::from_utf8(
-                    match Command::new("sh").arg("-c").arg("git rev-parse @").current_dir(repo_path).output() {
-                        Ok(a) => a.stdout,
-                        Err(_) => continue,
-                    }
-                ).expect("repo hash is not UTF-8 compatible");
-            if previous_head_hash != new_head_hash.trim() {
-                return true;
+        // Repo hashes are stored last, so having none means the previous load never finished.
+        if stored.is_empty() {
+            return RepoChanges::Everything;
+        }
+
+        // Versions are tagged with their repository, but an added or removed repository may change the recommended
+        // version of any package it holds, as well as the masks that apply to it, so it still requires a full reload.
+        let repo_paths: Vec<String> = repos.iter().map(|repo| repo.location.to_string_lossy().into_owned()).collect();
+        if repo_paths.len() != stored.len() || repo_paths.iter().any(|repo_path| !stored.contains_key(repo_path)) {
+            println!("*Configured repositories have changed*");
+            return RepoChanges::Everything;
+        }
+
+        let mut any_change = false;
+        let mut packages = HashSet::new();
+        for (repo, repo_path) in repos.iter().zip(&repo_paths) {
+            let (ref previous_kind, ref previous_fingerprint) = stored[repo_path];
+            let (kind, fingerprint) = repo_fingerprint(&repo.location);
+            if previous_kind == kind && *previous_fingerprint == fingerprint {
+                continue;
+            }
+
+            any_change = true;
+            if previous_kind != "git" || kind != "git" {
+                return RepoChanges::Everything;
+            }
+            let paths = match git_changed_paths(repo_path, previous_fingerprint, &fingerprint) {
+                Some(paths) => paths,
+                None => return RepoChanges::Everything,
+            };
+            if paths.iter().any(|path| path == "profiles/package.mask") {
+                let mut statement = self.prepare("SELECT category, name FROM package_masks WHERE repository = ?1").expect("sql cannot be converted to a C string");
+                let previous = statement.query_map(&[&repo.name], |row| Ok((row.get(0)?, row.get(1)?))).expect("failed to query database");
+                packages.extend(previous.filter_map(|package| package.ok()));
+                packages.extend(scanner::read_package_mask(&repo.location).into_iter().map(|(_, atom)| (atom.category, atom.name)));
+            }
+            packages.extend(paths.iter().filter_map(|path| scanner::package_for_path(path)));
+        }
+
+        if any_change {
+            RepoChanges::Packages(packages)
+        } else {
+            RepoChanges::None
+        }
+    }
+
+    fn installed_changed<P: AsRef<Path>>(&self, root: P) -> bool {
+        let stored = self.query_row("SELECT fingerprint FROM vdb_fingerprints WHERE root = ?1",
+                                    &[&root.as_ref().to_string_lossy().into_owned()], |row| row.get::<_, String>(0));
+        match stored {
+            Ok(fingerprint) => fingerprint != vdb::fingerprint(root),
+            Err(_) => true,
+        }
+    }
+}
+
+/// Stores the versions, ebuild paths, search index entries and recommended versions of `ebuilds`, which must hold
+/// every ebuild of the packages they belong to, ordered from the highest priority repository to the lowest. Runs inside
+/// the caller's transaction, and after `parse_for_masks`, as masked versions are never recommended.
+fn insert_ebuilds<I: IntoIterator<Item = Ebuild>>(conn: &Connection, accept_keywords: &[String], ebuilds: I) {
+    let arch = current_arch(accept_keywords).unwrap_or("").to_owned();
+    let mut recommended: HashMap<(String, String), (Version, String)> = HashMap::new();
+    let mut masks: HashMap<(String, String), Vec<Atom>> = HashMap::new();
+
+    let mut package_statement = conn.prepare("INSERT OR IGNORE INTO packages (category, name) VALUES (?1, ?2)").expect("sql cannot be converted to a C string");
+    let mut index_statement = conn.prepare("INSERT INTO packages_fts (category, name, description, homepage, long_description)
+                                            VALUES (?1, ?2, ?3, ?4, ?5)").expect("sql cannot be converted to a C string");
+    let mut version_statement = conn.prepare("INSERT OR IGNORE INTO all_packages (category, name, version, repository, slot, keywords, description, arch_keyword, accepted)
+                                              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)").expect("sql cannot be converted to a C string");
+    let mut ebuild_statement = conn.prepare("INSERT OR IGNORE INTO ebuilds (category, name, version, repository, ebuild_path)
+                                             VALUES (?1, ?2, ?3, ?4, ?5)").expect("sql cannot be converted to a C string");
+
+    for ebuild in ebuilds {
+        let inserted = package_statement.execute(&[&ebuild.category, &ebuild.name]).expect("failed to insert data into packages table");
+        if inserted == 1 {
+            let long_description = ebuild.path.parent()
+                .and_then(metadata::read_metadata)
+                .map(|metadata| metadata.long_description)
+                .unwrap_or_default();
+            index_statement.execute(&[&ebuild.category, &ebuild.name, &ebuild.description, &ebuild.homepage, &long_description])
+                           .expect("failed to insert data into packages_fts table");
+        }
+
+        let accepted = keywords_accepted(&ebuild.keywords, accept_keywords);
+        version_statement.execute(params![&ebuild.category, &ebuild.name, &ebuild.version.as_str(), &ebuild.repository, &ebuild.slot, &ebuild.keywords,
+                                          &ebuild.description, &arch_keyword(&ebuild.keywords, &arch), &accepted])
+                         .expect("failed to insert data into all_packages table");
+        ebuild_statement.execute(params![&ebuild.category, &ebuild.name, &ebuild.version.as_str(), &ebuild.repository, &ebuild.path.to_str()])
+                        .expect("failed to insert data into ebuilds table");
+
+        // On equal versions the first, and so highest priority, repository is recommended.
+        let package_masks = masks.entry((ebuild.category.clone(), ebuild.name.clone()))
+                                 .or_insert_with(|| package_masks(conn, &ebuild.category, &ebuild.name));
+        let masked = package_masks.iter().any(|mask| mask.matches(&ebuild.category, &ebuild.name, &ebuild.version, &ebuild.slot, &ebuild.repository));
+        if accepted && !masked {
+            let (version, repository) = (ebuild.version, ebuild.repository);
+            let best = recommended.entry((ebuild.category, ebuild.name)).or_insert_with(|| (version.clone(), repository.clone()));
+            if version > best.0 {
+                *best = (version, repository);
             }
         }
-        false
     }
 
-    fn tables_exist(&self) -> bool {
-        let mut statement = self.prepare("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'all_packages'").expect("sql cannot be converted to a C string");
-        let mut query_all_packages = statement.query(&[]).expect("failed to query database");
+    let mut recommended_statement = conn.prepare("INSERT INTO recommended_packages (category, name, version, repository)
+                                                  VALUES (?1, ?2, ?3, ?4)").expect("sql cannot be converted to a C string");
+    for ((category, name), (version, repository)) in recommended {
+        recommended_statement.execute(params![&category, &name, &version.as_str(), &repository])
+                             .expect("failed to insert data into recommended_packages table");
+    }
+}
+
+/// Fills in the versions of each package in `rows`.
+fn with_versions(conn: &Connection, mut rows: Vec<PackageRow>) -> Result<Vec<PackageRow>, Error> {
+    for row in &mut rows {
+        row.versions = conn.versions_of(&row.category, &row.name)?;
+    }
+    Ok(rows)
+}
+
+/// The `profiles/package.mask` entries of every repository that mention the package `category/name`.
+fn package_masks(conn: &Connection, category: &str, name: &str) -> Vec<Atom> {
+    let mut statement = conn.prepare("SELECT atom FROM package_masks WHERE category = ?1 AND name = ?2").expect("sql cannot be converted to a C string");
+    let masks = statement.query_map(&[&category, &name], |row| row.get::<_, String>(0)).expect("failed to query database")
+        .filter_map(|atom| atom.ok())
+        .filter_map(|atom| atom.parse().ok())
+        .collect();
+    masks
+}
+
+/// Resolves the status of every atom below `spec`.
+///
+/// An any-of group is as satisfied as its best child and an all-of group as its worst, while USE conditionals are left
+/// without a status.
+fn dependency_node(conn: &Connection, spec: &DepSpec) -> DependencyNode {
+    let resolve = |children: &[DepSpec]| -> Vec<DependencyNode> { children.iter().map(|child| dependency_node(conn, child)).collect() };
+    match *spec {
+        DepSpec::Atom(ref text, ref atom) => DependencyNode { label: text.clone(), status: Some(atom_status(conn, atom)), children: Vec::new() },
+        DepSpec::AnyOf(ref children) => {
+            let children = resolve(children);
+            DependencyNode { label: "any of".to_owned(), status: children.iter().filter_map(|child| child.status).max(), children }
+        }
+        DepSpec::AllOf(ref children) => {
+            let children = resolve(children);
+            DependencyNode { label: "all of".to_owned(), status: children.iter().filter_map(|child| child.status).min(), children }
+        }
+        DepSpec::UseConditional { ref flag, enabled, ref children } => {
+            let state = if enabled { "enabled" } else { "disabled" };
+            DependencyNode { label: format!("if {} is {}", flag, state), status: None, children: resolve(children) }
+        }
+    }
+}
+
+/// Whether a version matching `atom` is installed, or else available from any repository.
+fn atom_status(conn: &Connection, atom: &Atom) -> DependencyStatus {
+    let matches = |query: &str| {
+        let mut statement = conn.prepare(query).expect("sql cannot be converted to a C string");
+        let mut rows = statement.query_map(&[&atom.category, &atom.name], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+            .expect("failed to query database");
+        rows.any(|row| match row {
+            Ok((version, slot, repository)) => {
+                version.parse::<Version>().ok().map_or(false, |version| atom.matches(&atom.category, &atom.name, &version, &slot, &repository))
+            }
+            Err(_) => false,
+        })
+    };
+
+    if matches("SELECT version, slot, repository FROM installed_packages WHERE category = ?1 AND name = ?2") {
+        DependencyStatus::Installed
+    } else if matches("SELECT version, slot, repository FROM all_packages WHERE category = ?1 AND name = ?2") {
+        DependencyStatus::Available
+    } else {
+        DependencyStatus::Missing
+    }
+}
+
+/// An `AND` clause keeping only rows of `table` whose package is available from the repository bound to placeholder
+/// `placeholder`, or every row when that is bound to `NULL`.
+fn repository_filter(table: &str, placeholder: usize) -> String {
+    format!("AND (?{placeholder} IS NULL OR EXISTS (SELECT 1 FROM all_packages AS provided
+                                                   WHERE provided.repository = ?{placeholder}
+                                                   AND provided.category = {table}.category
+                                                   AND provided.name = {table}.name))",
+            placeholder = placeholder, table = table)
+}
+
+/// Runs a query whose first six columns make up a row of the package list, binding `params` to its placeholders.
+fn query_package_rows(conn: &Connection, query: &str, params: &[&dyn ToSql]) -> Result<Vec<PackageRow>, Error> {
+    let mut statement = conn.prepare(query)?;
+    let packages = statement.query_and_then(params, PackageRow::from_row)?.collect::<Result<_, _>>()?;
+    Ok(packages)
+}
+
+/// Registers the `gentoo_version` collation, so that queries can `ORDER BY version COLLATE gentoo_version`.
+pub fn register_version_collation(conn: &Connection) {
+    conn.create_collation("gentoo_version", version::collate).expect("failed to register gentoo_version collation");
+}
 
-        let mut statement = self.prepare("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'installed_packages'").expect("sql cannot be converted to a C string");
-        let mut query_installed_packages = statement.query(&[]).expect("failed to query database");
+/// Asks portage which keywords are accepted, such as `amd64 ~amd64`.
+fn get_accept_keywords() -> Vec<String> {
+    let output = match Command::new("portageq").args(&["envvar", "ACCEPT_KEYWORDS"]).output() {
+        Ok(output) => output,
+        Err(why) => {
+            eprintln!("failed to get ACCEPT_KEYWORDS, so no version will be recommended: {}", why);
+            return Vec::new();
+        }
+    };
+    String::from_utf8_lossy(&output.stdout).split_whitespace().map(|keyword| keyword.to_owned()).collect()
+}
 
-        let mut statement = self.prepare("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'recommended_packages'").expect("sql cannot be converted to a C string");
-        let mut query_recommended_packages = statement.query(&[]).expect("failed to query database");
+/// Whether an ebuild with the given `KEYWORDS` is visible under `accept_keywords`.
+fn keywords_accepted(keywords: &str, accept_keywords: &[String]) -> bool {
+    keywords.split_whitespace().any(|keyword| {
+        accept_keywords.iter().any(|accepted| {
+            accepted == keyword || accepted == "**" || (accepted == "*" && !keyword.starts_with('~') && !keyword.starts_with('-'))
+                || (accepted == "~*" && keyword.starts_with('~'))
+        })
+    })
+}
 
-        let mut statement = self.prepare("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'portage_sets'").expect("sql cannot be converted to a C string");
-        let mut query_portage_sets = statement.query(&[]).expect("failed to query database");
+/// The architecture being built for, taken as the first stable keyword of `accept_keywords`, such as `amd64`.
+fn current_arch(accept_keywords: &[String]) -> Option<&str> {
+    accept_keywords.iter()
+        .map(|keyword| &**keyword)
+        .find(|keyword| !keyword.starts_with('~') && !keyword.starts_with('-') && !keyword.contains('*'))
+}
 
-        let mut statement = self.prepare("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'ebuilds'").expect("sql cannot be converted to a C string");
-        let mut query_ebuilds = statement.query(&[]).expect("failed to query database");
+/// The entry of `keywords` for `arch`, such as `~amd64`, or `-*` when the ebuild is disabled on every architecture
+/// not listed. Empty when the ebuild is not keyworded for `arch` at all.
+fn arch_keyword(keywords: &str, arch: &str) -> String {
+    let mut fallback = "";
+    for keyword in keywords.split_whitespace() {
+        if keyword.trim_start_matches(|c| c == '~' || c == '-') == arch {
+            return keyword.to_owned();
+        } else if keyword == "-*" {
+            fallback = keyword;
+        }
+    }
+    fallback.to_owned()
+}
+
+/// The slot without its sub-slot, so `0/1.2` becomes `0`.
+fn main_slot(slot: &str) -> &str {
+    slot.split('/').next().unwrap_or(slot)
+}
 
-        let mut statement = self.prepare("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'repo_hashes'").expect("sql cannot be converted to a C string");
-        let mut query_repo_hashes = statement.query(&[]).expect("failed to query database");
+/// Identifies the current state of the repository at `repo_path`, returning the kind of fingerprint along with it.
+///
+/// Git checkouts are identified by their `HEAD` commit. Trees synced with rsync carry `metadata/timestamp.chk` or
+/// `metadata/timestamp.x`, which change on every sync. Anything else falls back to the newest mtime among its
+/// category and package directories, which rsync and manual edits alike update when replacing files.
+fn repo_fingerprint(repo_path: &Path) -> (&'static str, String) {
+    if let Some(head_hash) = git_head(repo_path) {
+        return ("git", head_hash);
+    }
 
-        if query_all_packages.next().unwrap().unwrap().get::<_, i32>(0) == 1 &&
-           query_installed_packages.next().unwrap().unwrap().get::<_, i32>(0) == 1 &&
-           query_recommended_packages.next().unwrap().unwrap().get::<_, i32>(0) == 1 &&
-           query_portage_sets.next().unwrap().unwrap().get::<_, i32>(0) == 1 &&
-           query_ebuilds.next().unwrap().unwrap().get::<_, i32>(0) == 1 &&
-           query_repo_hashes.next().unwrap().unwrap().get::<_, i32>(0) == 1 {
-               return true;
+    for timestamp_file in &["metadata/timestamp.chk", "metadata/timestamp.x"] {
+        let mut timestamp = String::new();
+        if fs::File::open(repo_path.join(timestamp_file)).and_then(|mut file| file.read_to_string(&mut timestamp)).is_ok() {
+            return ("timestamp", timestamp.trim().to_owned());
         }
-        false
     }
+
+    // Categories and packages are the two directory levels an added or removed ebuild touches.
+    ("mtime", vdb::newest_directory_mtime(repo_path, 2).to_string())
+}
+
+/// The commit checked out in the git repository at `repo_path`.
+fn git_head(repo_path: &Path) -> Option<String> {
+    let output = Command::new("git").args(&["rev-parse", "HEAD"]).current_dir(repo_path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok().map(|hash| hash.trim().to_owned())
+}
+
+/// The repository-relative paths of the files that differ between two commits, if both are known.
+fn git_changed_paths(repo_path: &str, from: &str, to: &str) -> Option<Vec<String>> {
+    if from.is_empty() || to.is_empty() {
+        return None;
+    }
+    let output = Command::new("git").args(&["diff", "--name-only", "--no-renames", from, to]).current_dir(repo_path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).lines().map(|path| path.to_owned()).collect())
 }
 
-pub fn get_file_list(package: &str) -> String {
-    String::from_utf8(Command::new("sh")
-            .arg("-c")
-            .arg(format!("qlist {}", package))
-            .output()
-            .expect("failed to get qlist output")
-            .stdout
-        ).expect("repo names are not UTF-8 compatible")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures;
+    use schema;
+
+    /// A database holding the fixture repository, with both stable and testing keywords accepted.
+    fn fixture_database() -> Connection {
+        let conn = Connection::open_in_memory().expect("failed to open connection");
+        register_version_collation(&conn);
+        schema::migrate(&conn);
+
+        let repos = vec![Repository {
+            name: "portix-test".to_owned(),
+            location: fixtures::repo(),
+            priority: 0,
+            masters: Vec::new(),
+            sync_type: None,
+            sync_uri: None,
+            auto_sync: false,
+        }];
+        conn.parse_for_masks(&repos);
+        conn.execute_batch("BEGIN").unwrap();
+        insert_ebuilds(&conn, &["amd64".to_owned(), "~amd64".to_owned()], scanner::scan_repository(&repos[0].location, &repos[0].name));
+        conn.execute_batch("COMMIT").unwrap();
+        conn
+    }
+
+    #[test]
+    fn does_not_recommend_masked_versions() {
+        let conn = fixture_database();
+        let recommended: String = conn.query_row("SELECT version FROM recommended_packages WHERE category = 'app-misc' AND name = 'hello'",
+                                                 NO_PARAMS, |row| row.get(0)).expect("hello has no recommended version");
+        assert_eq!(recommended, "1.0");
+    }
+
+    #[test]
+    fn reads_sets_below_the_config_root() {
+        let conn = fixture_database();
+        conn.parse_for_sets(fixtures::root());
+        let mut statement = conn.prepare("SELECT portage_set, atom FROM portage_sets").unwrap();
+        let sets: Vec<(String, String)> = statement.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(sets, vec![("greetings".to_owned(), ">=app-misc/hello-1.0".to_owned())]);
+
+        // A root without a sets directory has no sets.
+        conn.parse_for_sets(fixtures::repo());
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM portage_sets", NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn lists_set_packages_with_their_category_and_versions() {
+        let conn = fixture_database();
+        conn.parse_for_sets(fixtures::root());
+        let rows = conn.packages_in_category(&View::Sets, None, "greetings").expect("failed to list the set");
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].category.as_str(), rows[0].name.as_str()), ("app-misc", "hello"));
+        assert!(!rows[0].versions.is_empty());
+    }
 }
@@ -0,0 +1,183 @@
+use std::fmt;
+
+use atom::{Atom, ParseAtomError};
+
+/// A node of a dependency specification such as `foo? ( cat/a ) || ( cat/b cat/c )`.
+#[derive(Clone, Debug)]
+pub enum DepSpec {
+    /// An atom along with the text it was parsed from.
+    Atom(String, Box<Atom>),
+    /// `( ... )`, requiring every child.
+    AllOf(Vec<DepSpec>),
+    /// `|| ( ... )`, requiring at least one child.
+    AnyOf(Vec<DepSpec>),
+    /// `flag? ( ... )` or `!flag? ( ... )`, requiring the children when the flag is enabled, or disabled when
+    /// `enabled` is `false`.
+    UseConditional { flag: String, enabled: bool, children: Vec<DepSpec> },
+}
+
+#[derive(Debug)]
+pub enum ParseDepError {
+    Atom(ParseAtomError),
+    /// A `(` without its `)`, a `)` without its `(`, or an operator not followed by a group.
+    UnbalancedGroup,
+}
+
+impl fmt::Display for ParseDepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseDepError::Atom(ref why) => write!(f, "{}", why),
+            ParseDepError::UnbalancedGroup => write!(f, "unbalanced parentheses in dependency specification"),
+        }
+    }
+}
+
+impl From<ParseAtomError> for ParseDepError {
+    fn from(why: ParseAtomError) -> ParseDepError {
+        ParseDepError::Atom(why)
+    }
+}
+
+/// Parses a dependency specification, as found in `DEPEND` and its siblings.
+pub fn parse(text: &str) -> Result<Vec<DepSpec>, ParseDepError> {
+    let mut tokens = text.split_whitespace();
+    let specs = parse_group(&mut tokens)?;
+    match specs {
+        (specs, false) => Ok(specs),
+        (_, true) => Err(ParseDepError::UnbalancedGroup),
+    }
+}
+
+/// Every atom of `specs` along with its text, whatever group or conditional it is nested in, and whether it is one of
+/// the alternatives of an any-of group.
+pub fn atoms(specs: &[DepSpec]) -> Vec<(&str, &Atom, bool)> {
+    fn collect<'a>(specs: &'a [DepSpec], any_of: bool, atoms: &mut Vec<(&'a str, &'a Atom, bool)>) {
+        for spec in specs {
+            match *spec {
+                DepSpec::Atom(ref text, ref atom) => atoms.push((&**text, &**atom, any_of)),
+                DepSpec::AnyOf(ref children) => collect(children, true, atoms),
+                DepSpec::AllOf(ref children) | DepSpec::UseConditional { ref children, .. } => collect(children, any_of, atoms),
+            }
+        }
+    }
+
+    let mut atoms = Vec::new();
+    collect(specs, false, &mut atoms);
+    atoms
+}
+
+/// Parses tokens up to the end of the text or a closing `)`, returning whether it stopped at the latter.
+fn parse_group<'a, I: Iterator<Item = &'a str>>(tokens: &mut I) -> Result<(Vec<DepSpec>, bool), ParseDepError> {
+    let mut specs = Vec::new();
+    while let Some(token) = tokens.next() {
+        let spec = if token == ")" {
+            return Ok((specs, true));
+        } else if token == "(" {
+            DepSpec::AllOf(parse_subgroup(tokens)?)
+        } else if token == "||" {
+            expect_open(tokens)?;
+            DepSpec::AnyOf(parse_subgroup(tokens)?)
+        } else if token.ends_with('?') {
+            expect_open(tokens)?;
+            let (flag, enabled) = if token.starts_with('!') { (&token[1..token.len() - 1], false) } else { (&token[..token.len() - 1], true) };
+            DepSpec::UseConditional { flag: flag.to_owned(), enabled, children: parse_subgroup(tokens)? }
+        } else {
+            DepSpec::Atom(token.to_owned(), Box::new(token.parse()?))
+        };
+        specs.push(spec);
+    }
+    Ok((specs, false))
+}
+
+/// Parses the rest of a group whose `(` was already consumed.
+fn parse_subgroup<'a, I: Iterator<Item = &'a str>>(tokens: &mut I) -> Result<Vec<DepSpec>, ParseDepError> {
+    match parse_group(tokens)? {
+        (specs, true) => Ok(specs),
+        (_, false) => Err(ParseDepError::UnbalancedGroup),
+    }
+}
+
+fn expect_open<'a, I: Iterator<Item = &'a str>>(tokens: &mut I) -> Result<(), ParseDepError> {
+    match tokens.next() {
+        Some("(") => Ok(()),
+        _ => Err(ParseDepError::UnbalancedGroup),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `specs` back out with single spaces, so that the shape of the parsed tree can be compared as text.
+    fn render(specs: &[DepSpec]) -> String {
+        let group = |children: &[DepSpec]| if children.is_empty() { "( )".to_owned() } else { format!("( {} )", render(children)) };
+        specs.iter()
+            .map(|spec| match *spec {
+                DepSpec::Atom(ref text, _) => text.clone(),
+                DepSpec::AllOf(ref children) => group(children),
+                DepSpec::AnyOf(ref children) => format!("|| {}", group(children)),
+                DepSpec::UseConditional { ref flag, enabled, ref children } => {
+                    format!("{}{}? {}", if enabled { "" } else { "!" }, flag, group(children))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn parsed(text: &str) -> Vec<DepSpec> {
+        parse(text).unwrap_or_else(|why| panic!("{}: {}", text, why))
+    }
+
+    #[test]
+    fn parses_empty_groups() {
+        for text in &["|| ( )", "doc? ( )", "!doc? ( )", "( )"] {
+            assert_eq!(render(&parsed(text)), *text);
+        }
+
+        match parsed("!test? ( )")[0] {
+            DepSpec::UseConditional { ref flag, enabled, ref children } => {
+                assert_eq!((flag.as_str(), enabled, children.len()), ("test", false, 0));
+            }
+            ref other => panic!("expected a USE conditional, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_nested_groups() {
+        let text = "app-misc/a ssl? ( || ( dev-libs/openssl ( dev-libs/libressl !doc? ( app-misc/b ) ) ) ) \
+                    || ( app-misc/c app-misc/d )";
+        assert_eq!(render(&parsed(text)), text.split_whitespace().collect::<Vec<_>>().join(" "));
+
+        let specs = parsed("ssl? (\n\tdev-libs/openssl\n)");
+        match specs[0] {
+            DepSpec::UseConditional { ref children, .. } => assert_eq!(render(children), "dev-libs/openssl"),
+            ref other => panic!("expected a USE conditional, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_unbalanced_groups() {
+        for text in &["( a/b", "a/b )", "|| a/b", "doc? a/b", "|| ( a/b", "( ( a/b )"] {
+            match parse(text) {
+                Err(ParseDepError::UnbalancedGroup) => (),
+                other => panic!("{} gave {:?}", text, other),
+            }
+        }
+
+        match parse("( app-misc/hello-1.0 )") {
+            Err(ParseDepError::Atom(_)) => (),
+            other => panic!("gave {:?}", other),
+        }
+    }
+
+    #[test]
+    fn flags_alternatives_among_atoms() {
+        let specs = parsed("app-misc/a || ( app-misc/b doc? ( app-misc/c ) ( app-misc/d ) ) test? ( app-misc/e )");
+        let flagged: Vec<(&str, bool)> = atoms(&specs).into_iter().map(|(text, _, any_of)| (text, any_of)).collect();
+        assert_eq!(flagged, vec![("app-misc/a", false), ("app-misc/b", true), ("app-misc/c", true), ("app-misc/d", true),
+                                 ("app-misc/e", false)]);
+
+        let (_, atom, _) = atoms(&specs)[0];
+        assert_eq!((atom.category.as_str(), atom.name.as_str()), ("app-misc", "a"));
+    }
+}
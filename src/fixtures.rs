@@ -0,0 +1,11 @@
+use std::path::{Path, PathBuf};
+
+/// The repository under `tests/fixtures/repo`, with ebuilds, a metadata cache and profiles.
+pub fn repo() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/repo")
+}
+
+/// The root under `tests/fixtures/root`, with installed packages in `var/db/pkg` and configuration in `etc/portage`.
+pub fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/root")
+}
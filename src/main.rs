@@ -1,12 +1,21 @@
 extern crate gtk;
 //extern crate glib;
+#[macro_use]
 extern crate rusqlite;
 
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::thread;
 use std::rc::Rc;
-use std::sync::mpsc::{channel, TryRecvError};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender, TryRecvError};
 
-use backend::PortixConnection;
+use atom::Atom;
+use backend::{CategoryCount, DependencyNode, EbuildText, PackageRow, PortixConnection, RepoChanges, ReverseDependency, Summary, UseFlagRow, View};
+use repos::Repository;
+use settings::Settings;
+use vdb::ContentsEntry;
 
 use gtk::prelude::*;
 use gtk::{Window, WindowType};
@@ -14,132 +23,273 @@ use gtk::{Window, WindowType};
 
 use rusqlite::Connection;
 
+mod atom;
 mod backend;
-
+mod depend;
+#[cfg(test)]
+mod fixtures;
+mod metadata;
+mod repos;
+mod scanner;
+mod schema;
+mod settings;
+mod useflags;
+mod vdb;
+mod version;
+
+/// Requests for the database thread. Views take the repository to filter by, if any.
 enum Request {
-    Ebuilds(String),
-    FileList(String),
-    ModelCategory(String),
-    Other(String),
+    Categories(View, Option<String>),
+    Ebuild(Package),
+    InstalledFiles(Package),
+    Summary(Package),
+    Dependencies(Package),
+    UseFlags(Package),
+    Packages(View, Option<String>, String),
+    RefreshInstalled,
 }
 
+// Responses are sent one at a time, so the size of the largest variant does not matter.
+#[allow(clippy::large_enum_variant)]
 enum Response {
-    Other(Vec<(String, String, String, String)>),
-    ModelCategory(Vec<(String, i32)>),
-    StringQuery(String)
+    Categories(Result<Vec<CategoryCount>, backend::Error>),
+    Ebuild(Result<EbuildText, backend::Error>),
+    InstalledFiles(Result<Vec<ContentsEntry>, backend::Error>),
+    Summary(Result<Summary, backend::Error>),
+    Dependencies(Result<(Vec<DependencyNode>, Vec<ReverseDependency>), backend::Error>),
+    UseFlags(Result<Vec<UseFlagRow>, backend::Error>),
+    Packages(Result<Vec<PackageRow>, backend::Error>),
+    /// Whether the installed package database had changed and was loaded again.
+    RefreshInstalled(bool),
 }
 
-fn main() {
-    let (db_request_sender, db_request_receiver) = channel::<Request>();
-    let (db_response_sender, db_response_receiver) = channel::<Response>();
+/// A package version picked in the package list.
+struct Package {
+    category: String,
+    name: String,
+    version: String,
+    repository: String,
+}
 
-    thread::spawn(move || {
-        let conn = Connection::open(backend::DB_PATH).expect("failed to open connection");
-        rusqlite::vtab::csvtab::load_module(&conn).unwrap();
-        let conn = if !conn.tables_exist() {
-            loading_tables(conn)
-        } else if conn.tables_need_reloading() {
-            println!("*Database needs reloading again*");
-            loading_tables(conn)
-        } else {
-            conn
-        };
+/// The views making up the pages of the package notebook.
+#[derive(Clone)]
+struct PackagePages {
+    summary: gtk::TextView,
+    dependencies: gtk::TreeView,
+    installed_files: gtk::TreeView,
+    ebuild: gtk::TextView,
+    use_flags: gtk::TreeView,
+}
 
-        loop {
-            let selection = match db_request_receiver.recv() {
-                Ok(selection) => selection,
-                Err(why) => {
-                    eprintln!("error when receiving: {}", why);
-                    return;
-                }
-            };
+/// Independent streams of requests; a new request makes any older one in the same lane stale.
+#[derive(Clone, Copy)]
+enum Lane {
+    Categories,
+    Packages,
+    Details,
+    Refresh,
+}
+
+/// A request tagged with its ID and the channel its response goes back on.
+struct Job {
+    id: usize,
+    lane: Lane,
+    request: Request,
+    reply: Sender<Response>,
+}
+
+/// The GUI's handle on the database thread.
+///
+/// Every request gets its own reply channel, so a response can only reach the handler that asked for it.
+#[derive(Clone)]
+struct Database {
+    jobs: Sender<Job>,
+    next_id: Rc<Cell<usize>>,
+    latest: Arc<[AtomicUsize; 4]>,
+}
+
+impl Database {
+    /// Sends `request` and calls `on_response` with its response, unless a newer request in the same lane has been
+    /// sent by the time it arrives. Stale requests that are still queued are skipped by the database thread.
+    fn send<F: Fn(Response) + 'static>(&self, lane: Lane, request: Request, on_response: F) {
+        let id = self.supersede(lane);
+        let (reply, response) = channel();
+        if self.jobs.send(Job { id, lane, request, reply }).is_err() {
+            eprintln!("database thread has stopped");
+            return;
+        }
 
-            match selection {
-                Request::Ebuilds(query) => {
-                    db_response_sender.send(Response::StringQuery(conn.get_ebuild_with_query(&query)));
-                },
-                Request::FileList(query) => {
-                    db_response_sender.send(Response::StringQuery(backend::get_file_list(&query)));
+        let latest = self.latest.clone();
+        gtk::timeout_add(100, move || {
+            if latest[lane as usize].load(Ordering::SeqCst) != id {
+                return Continue(false);
+            }
+            match response.try_recv() {
+                Ok(response) => {
+                    on_response(response);
+                    Continue(false)
                 }
-                Request::ModelCategory(query) => {
-                    let mut statement = match conn.prepare(&query) {
-                        Ok(selection) => selection,
-                        Err(why) => {
-                            eprintln!("failed to prepare query: {}", why);
-                            continue
-                        }
-                    };
+                Err(TryRecvError::Empty) => Continue(true),
+                Err(why) => {
+                    eprintln!("failed to receive: {}", why);
+                    Continue(false)
+                }
+            }
+        });
+    }
 
-                    let mut rows = match statement.query(&[]) {
-                        Ok(selection) => selection,
-                        Err(why) => {
-                            eprintln!("failed to query: {}", why);
-                            continue
-                        }
-                    };
+    /// Abandons whatever request is pending in `lane`.
+    fn cancel(&self, lane: Lane) {
+        self.supersede(lane);
+    }
 
-                    let mut response = Vec::new();
-                    while let Some(Ok(row)) = rows.next() {
-                        response.push((row.get::<_, String>(0), row.get::<_, i32>(1)));
-                    }
+    fn supersede(&self, lane: Lane) -> usize {
+        let id = self.next_id.get() + 1;
+        self.next_id.set(id);
+        self.latest[lane as usize].store(id, Ordering::SeqCst);
+        id
+    }
+}
 
-                    db_response_sender.send(Response::ModelCategory(response));
+fn main() {
+    let settings = Settings::from_env();
+    let repositories = repos::read_repos_conf(&settings.config_root);
+    let (jobs, job_receiver) = channel::<Job>();
+    let latest = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)]);
+    let db = Database { jobs, next_id: Rc::new(Cell::new(0)), latest: latest.clone() };
+
+    let db_repositories = repositories.clone();
+    let watched_root = settings.root.clone();
+    thread::spawn(move || {
+        let repositories = db_repositories;
+        let use_config = useflags::read_use_config(&settings.config_root);
+        let conn = Connection::open(&settings.db_path).expect("failed to open connection");
+        backend::register_version_collation(&conn);
+        let conn = if schema::migrate(&conn) {
+            loading_tables(conn, &repositories, &settings)
+        } else {
+            match conn.repo_changes(&repositories) {
+                RepoChanges::Everything => {
+                    println!("*Database needs reloading again*");
+                    loading_tables(conn, &repositories, &settings)
                 }
-                Request::Other(query) => {
-                    let mut statement = match conn.prepare(&query) {
-                        Ok(selection) => selection,
-                        Err(why) => {
-                            eprintln!("failed to prepare query: {}", why);
-                            continue
-                        }
-                    };
+                RepoChanges::Packages(packages) => updating_tables(conn, &repositories, &packages, &settings),
+                RepoChanges::None => conn,
+            }
+        };
+        if conn.installed_changed(&settings.root) {
+            refreshing_installed(&conn, &settings);
+        }
 
-                    let mut rows = match statement.query(&[]) {
-                        Ok(selection) => selection,
-                        Err(why) => {
-                            eprintln!("failed to query: {}", why);
-                            continue
-                        }
-                    };
+        for job in job_receiver {
+            if latest[job.lane as usize].load(Ordering::SeqCst) != job.id {
+                continue; // superseded while it was queued
+            }
 
-                    let mut response = Vec::new();
-                    while let Some(Ok(row)) = rows.next() {
-                        response.push((row.get::<_, String>(0), row.get::<_, String>(1), row.get::<_, String>(2), row.get::<_, String>(3)));
+            let response = match job.request {
+                Request::Categories(view, repository) => Response::Categories(conn.category_counts(&view, repository.as_ref().map(|repository| &**repository))),
+                Request::Ebuild(package) => Response::Ebuild(conn.get_ebuild(&package.category, &package.name, &package.version, &package.repository)),
+                Request::InstalledFiles(package) => Response::InstalledFiles(conn.installed_files(&package.category, &package.name, &package.version)),
+                Request::Summary(package) => Response::Summary(conn.get_summary(&package.category, &package.name, &package.version, &package.repository)),
+                Request::Dependencies(package) => Response::Dependencies(
+                    conn.get_dependencies(&package.category, &package.name, &package.version, &package.repository)
+                        .and_then(|dependencies| Ok((dependencies, conn.reverse_dependencies(&package.category, &package.name)?)))
+                ),
+                Request::UseFlags(package) => {
+                    Response::UseFlags(conn.get_use_flags(&repositories, &use_config, &package.category, &package.name, &package.version, &package.repository))
+                }
+                Request::Packages(view, repository, category) => {
+                    Response::Packages(conn.packages_in_category(&view, repository.as_ref().map(|repository| &**repository), &category))
+                }
+                Request::RefreshInstalled => {
+                    let changed = conn.installed_changed(&settings.root);
+                    if changed {
+                        refreshing_installed(&conn, &settings);
                     }
-
-                    db_response_sender.send(Response::Other(response));
+                    Response::RefreshInstalled(changed)
                 }
             };
+            // The handler is gone if the request went stale while it was being answered.
+            let _ = job.reply.send(response);
         }
     });
 
-    let db_request = Rc::new(db_request_sender);
-    let db_response = Rc::new(db_response_receiver);
 
     if gtk::init().is_err() {
         println!("failed to initialize GTK.");
     }
 
-    fn loading_tables(conn: Connection) -> Connection {
-        println!("(1/4) Storing repo hash info into database...");
-        conn.store_repo_hashes();
+    fn loading_tables(conn: Connection, repositories: &[Repository], settings: &Settings) -> Connection {
+        // Masks are loaded first, as they decide which versions are recommended.
+        println!("(1/7) Loading package mask info into database...");
+        conn.parse_for_masks(repositories);
+        println!("Done");
+        println!("(2/7) Loading package and ebuild info into database...");
+        conn.parse_for_pkgs(repositories);
+        println!("Done");
+        println!("(3/7) Loading installed package info into database...");
+        conn.parse_for_installed(&settings.root);
+        println!("Done");
+        println!("(4/7) Loading upgradeable package info into database...");
+        conn.parse_for_upgrades();
         println!("Done");
-        println!("(2/4) Loading package info into database...");
-        conn.parse_for_pkgs();
+        println!("(5/7) Loading deprecated package info into database...");
+        conn.parse_for_deprecated();
         println!("Done");
-        println!("(3/4) Loading portage set info into database...");
-        conn.parse_for_sets();
+        println!("(6/7) Loading portage set info into database...");
+        conn.parse_for_sets(&settings.config_root);
         println!("Done");
-        println!("(4/4) Loading ebuild info into database...");
-        conn.parse_for_ebuilds();
+        // Stored last, so that an interrupted load is picked up again on the next start.
+        println!("(7/7) Storing repo hash info into database...");
+        conn.store_repo_hashes(repositories);
         println!("Done");
         conn
     }
 
+    fn refreshing_installed(conn: &Connection, settings: &Settings) {
+        println!("(1/3) Loading installed package info into database...");
+        conn.parse_for_installed(&settings.root);
+        println!("Done");
+        println!("(2/3) Loading upgradeable package info into database...");
+        conn.parse_for_upgrades();
+        println!("Done");
+        println!("(3/3) Loading deprecated package info into database...");
+        conn.parse_for_deprecated();
+        println!("Done");
+    }
+
+    fn updating_tables(conn: Connection, repositories: &[Repository], packages: &HashSet<(String, String)>, settings: &Settings) -> Connection {
+        println!("(1/6) Loading package mask info into database...");
+        conn.parse_for_masks(repositories);
+        println!("Done");
+        println!("(2/6) Updating {} changed packages in database...", packages.len());
+        conn.reindex_packages(repositories, packages);
+        println!("Done");
+        println!("(3/6) Loading upgradeable package info into database...");
+        conn.parse_for_upgrades();
+        println!("Done");
+        println!("(4/6) Loading deprecated package info into database...");
+        conn.parse_for_deprecated();
+        println!("Done");
+        println!("(5/6) Loading portage set info into database...");
+        conn.parse_for_sets(&settings.config_root);
+        println!("Done");
+        println!("(6/6) Storing repo hash info into database...");
+        conn.store_repo_hashes(repositories);
+        println!("Done");
+        conn
+    }
+
+    let repositories_item = gtk::MenuItem::new_with_label("Repositories");
+    let settings_menu = gtk::Menu::new();
+    settings_menu.append(&repositories_item);
+    let settings_item = gtk::MenuItem::new_with_label("Settings");
+    settings_item.set_submenu(Some(&settings_menu));
+
     let menubar = gtk::MenuBar::new();
-    menubar.append(&gtk::MenuItem::new_with_label(&"Actions"));
-    menubar.append(&gtk::MenuItem::new_with_label(&"Settings"));
-    menubar.append(&gtk::MenuItem::new_with_label(&"Help"));
+    menubar.append(&gtk::MenuItem::new_with_label("Actions"));
+    menubar.append(&settings_item);
+    menubar.append(&gtk::MenuItem::new_with_label("Help"));
 
     let toolbuttons: Vec<_> = {
         let icon_names_and_labels = [("list-add", "Emerge"), ("emblem-system", "Advance Emerge"), ("list-remove", "Unmerge"), ("view-refresh", "Sync"), ("media-seek-forward", "Upgrade"), ("media-playback-start", "Queue")];
@@ -168,14 +318,34 @@ fn main() {
     }
     combo_box.set_active(0); // Set "All Packages" to be default
 
+    let repository_box = gtk::ComboBoxText::new();
+    repository_box.append_text("All repositories");
+    for repo in &repositories {
+        repository_box.append_text(&repo.name);
+    }
+    repository_box.set_active(0);
+
     let search_entry = gtk::SearchEntry::new();
     search_entry.set_hexpand(true);
 
+    let search_scope_box = gtk::ComboBoxText::new();
+    for label in ["Names", "Descriptions", "Names and descriptions"].iter() {
+        search_scope_box.append_text(label);
+    }
+    search_scope_box.set_active(0);
+    let search_installed_only = gtk::CheckButton::new_with_label("Installed only");
+    let current_search: Rc<RefCell<Option<backend::Search>>> = Rc::new(RefCell::new(None));
+
     let hbox1 = gtk::Box::new(gtk::Orientation::Horizontal, 4);
     hbox1.add(&gtk::Label::new("View: "));
     hbox1.add(&combo_box);
+    hbox1.add(&repository_box);
     hbox1.add(&gtk::Button::new_with_label("Refresh"));
     hbox1.add(&search_entry);
+    hbox1.add(&search_scope_box);
+    hbox1.add(&search_installed_only);
+
+    let statusbar = gtk::Statusbar::new();
 
     pub fn make_tree_view_column(title: &str, column_number: i32) -> gtk::TreeViewColumn {
         let column = gtk::TreeViewColumn::new();
@@ -187,30 +357,305 @@ fn main() {
         column
     }
 
-    let column_category = make_tree_view_column("Categories", 0);
-    let column_pkg_num = make_tree_view_column("# Pkgs", 1);
+    /// Opens a window listing the configured repositories, highest priority first.
+    fn show_repositories(repositories: &[Repository]) {
+        let model = gtk::ListStore::new(&[gtk::Type::String, gtk::Type::String, gtk::Type::I32, gtk::Type::String, gtk::Type::String,
+                                          gtk::Type::String, gtk::Type::String]);
+        for repo in repositories {
+            model.insert_with_values(None, &[0, 1, 2, 3, 4, 5, 6], &[&repo.name, &repo.location.to_string_lossy().into_owned(), &repo.priority,
+                                                                     &repo.masters.join(" "), &repo.sync_type.clone().unwrap_or_default(),
+                                                                     &repo.sync_uri.clone().unwrap_or_default(), &if repo.auto_sync { "yes" } else { "no" }]);
+        }
 
-    let model_category = gtk::ListStore::new(&[gtk::Type::String, gtk::Type::U64]);
+        let tree_view = gtk::TreeView::new_with_model(&model);
+        for (column_number, &title) in ["Name", "Location", "Priority", "Masters", "Sync type", "Sync URI", "Auto-sync"].iter().enumerate() {
+            tree_view.append_column(&make_tree_view_column(title, column_number as i32));
+        }
+        let scrollable = gtk::ScrolledWindow::new(None, None);
+        scrollable.add(&tree_view);
+
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("Repositories");
+        window.set_default_size(900, 300);
+        window.add(&scrollable);
+        window.show_all();
+    }
 
-    db_request.send(Request::ModelCategory("SELECT category, count(DISTINCT name) as pkg_count
-                                      FROM all_packages
-                                      GROUP BY category".into()));
-    match db_response.recv() {
-        Ok(Response::ModelCategory(rows)) => {
-            for row in rows {
-                model_category.insert_with_values(None, &[0, 1], &[&row.0, &row.1]);
+    /// Shows why a request failed in the status bar, or clears the last failure once a request succeeds.
+    fn report<T>(statusbar: &gtk::Statusbar, result: Result<T, backend::Error>) -> Option<T> {
+        let context = statusbar.get_context_id("database");
+        statusbar.remove_all(context);
+        match result {
+            Ok(value) => Some(value),
+            Err(why) => {
+                eprintln!("{}", why);
+                statusbar.push(context, &why.to_string());
+                None
             }
         }
-        Ok(_) => {
-            eprintln!("invalid response");
-            return;
+    }
+
+    fn load_category_model(model_category: &gtk::ListStore, statusbar: &gtk::Statusbar, db: &Database, view: View, repository: Option<String>) {
+        model_category.clear();
+
+        let model_category = model_category.clone();
+        let statusbar = statusbar.clone();
+        db.send(Lane::Categories, Request::Categories(view, repository), move |response| match response {
+            Response::Categories(result) => {
+                for row in report(&statusbar, result).unwrap_or_default() {
+                    model_category.insert_with_values(None, &[0, 1], &[&row.category, &row.count]);
+                }
+            }
+            _ => eprintln!("invalid response"),
+        });
+    }
+
+    /// Fills the notebook page at index `page` with what it shows about a package.
+    fn load_package_page(pages: &PackagePages, statusbar: &gtk::Statusbar, db: &Database, page: u32, package: Package) {
+        let request = match page {
+            0 => Request::Summary(package),
+            1 => Request::Dependencies(package),
+            4 => Request::UseFlags(package),
+            2 => Request::InstalledFiles(package),
+            3 => Request::Ebuild(package),
+            _ => return,
+        };
+
+        let pages = pages.clone();
+        let statusbar = statusbar.clone();
+        db.send(Lane::Details, request, move |response| match response {
+            Response::Ebuild(result) => {
+                let ebuild = report(&statusbar, result);
+                pages.ebuild.get_buffer().unwrap().set_text(ebuild.as_ref().map_or("", |ebuild| &*ebuild.text));
+            }
+            Response::InstalledFiles(result) => {
+                let model = pages.installed_files.get_model().and_then(|model| model.downcast::<gtk::TreeStore>().ok()).unwrap();
+                model.clear();
+                show_installed_files(&model, &report(&statusbar, result).unwrap_or_default());
+            }
+            Response::Dependencies(result) => {
+                let model = pages.dependencies.get_model().and_then(|model| model.downcast::<gtk::TreeStore>().ok()).unwrap();
+                model.clear();
+                if let Some((dependencies, dependents)) = report(&statusbar, result) {
+                    for node in &dependencies {
+                        insert_dependency(&model, None, node);
+                    }
+                    // Installed packages that would be left with a broken dependency if the package were unmerged.
+                    let required_by = model.insert_with_values(None, None, &[0, 1], &[&format!("Required by ({})", dependents.len()), &""]);
+                    for dependent in &dependents {
+                        let label = format!("{}/{}-{} ({} {})", dependent.category, dependent.name, dependent.version, dependent.kind, dependent.atom);
+                        model.insert_with_values(Some(&required_by), None, &[0, 1], &[&label, &if dependent.any_of { "Alternative" } else { "Required" }]);
+                    }
+                }
+                pages.dependencies.expand_all();
+            }
+            Response::UseFlags(result) => {
+                let model = pages.use_flags.get_model().and_then(|model| model.downcast::<gtk::ListStore>().ok()).unwrap();
+                model.clear();
+                let state = |enabled: bool| if enabled { "enabled" } else { "disabled" };
+                for row in report(&statusbar, result).unwrap_or_default() {
+                    let default = match row.default {
+                        Some(true) => "+",
+                        Some(false) => "-",
+                        None => "",
+                    };
+                    model.insert_with_values(None, &[0, 1, 2, 3, 4, 5], &[&row.flag, &default, &state(row.enabled), &row.installed.map_or("", &state),
+                                                                          &if row.changed() { "*" } else { "" }, &row.description]);
+                }
+            }
+            Response::Summary(result) => {
+                pages.summary.get_buffer().unwrap().set_text("");
+                if let Some(summary) = report(&statusbar, result) {
+                    show_summary(&pages.summary, &summary);
+                }
+            }
+            _ => eprintln!("invalid response"),
+        });
+    }
+
+    /// Fills the Installed files page with the entries of a package's CONTENTS, nested under their directories.
+    fn show_installed_files(model: &gtk::TreeStore, entries: &[ContentsEntry]) {
+        /// The row of the directory at `path`, which is added along with its parents if CONTENTS did not list it first.
+        /// Top-level entries have no parent row and show their full path.
+        fn directory_row(model: &gtk::TreeStore, directories: &mut HashMap<String, gtk::TreeIter>, path: &str) -> Option<gtk::TreeIter> {
+            if let Some(iter) = directories.get(path) {
+                return Some(iter.clone());
+            }
+            let index = path.rfind('/')?;
+            let parent = directory_row(model, directories, &path[..index]);
+            let label = if parent.is_some() { &path[index + 1..] } else { path };
+            let iter = model.insert_with_values(parent.as_ref(), None, &[0, 1], &[&label, &"dir"]);
+            directories.insert(path.to_owned(), iter.clone());
+            Some(iter)
         }
-        Err(why) => {
-            eprintln!("failed to receive: {}", why);
-            return;
+
+        let mut directories = HashMap::new();
+        for entry in entries {
+            if entry.kind == "dir" {
+                directory_row(model, &mut directories, &entry.path);
+                continue;
+            }
+            let index = entry.path.rfind('/').unwrap_or(0);
+            let parent = directory_row(model, &mut directories, &entry.path[..index]);
+            let label = if parent.is_some() { &entry.path[index + 1..] } else { &*entry.path };
+            model.insert_with_values(parent.as_ref(), None, &[0, 1, 2, 3, 4],
+                                     &[&label, &entry.kind, &entry.md5.clone().unwrap_or_default(), &entry.mtime.map_or(String::new(), format_time),
+                                       &entry.target.clone().unwrap_or_default()]);
+        }
+    }
+
+    /// Formats seconds since the epoch as a UTC date and time, using Howard Hinnant's `civil_from_days` algorithm.
+    fn format_time(seconds: u64) -> String {
+        let (days, time) = ((seconds / 86_400) as i64, seconds % 86_400);
+        let shifted = days + 719_468; // days since 0000-03-01
+        let era = shifted / 146_097;
+        let day_of_era = shifted - era * 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153; // counting from March
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+        format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+    }
+
+    /// Adds a node of the Dependencies page, and everything below it, under `parent`.
+    fn insert_dependency(model: &gtk::TreeStore, parent: Option<&gtk::TreeIter>, node: &backend::DependencyNode) {
+        let status = node.status.map_or("", |status| status.as_str());
+        let iter = model.insert_with_values(parent, None, &[0, 1], &[&node.label, &status]);
+        for child in &node.children {
+            insert_dependency(model, Some(&iter), child);
+        }
+    }
+
+    /// Writes a summary into the Summary page, with links that open in the browser.
+    fn show_summary(view: &gtk::TextView, summary: &backend::Summary) {
+        let buffer = view.get_buffer().unwrap();
+        let mut iter = buffer.get_end_iter();
+        let link = |iter: &mut gtk::TextIter, uri: &str| {
+            if let Some(anchor) = buffer.create_child_anchor(iter) {
+                let button = gtk::LinkButton::new(uri);
+                view.add_child_at_anchor(&button, &anchor);
+                button.show();
+            }
+        };
+        // gtk-rs does not bind the variadic gtk_text_buffer_insert_with_tags_by_name.
+        let insert_tagged = |iter: &mut gtk::TextIter, text: &str, tag: &str| {
+            let start = iter.get_offset();
+            buffer.insert(iter, text);
+            buffer.apply_tag_by_name(tag, &buffer.get_iter_at_offset(start), iter);
+        };
+
+        insert_tagged(&mut iter, &format!("{}/{}-{}::{}\n", summary.category, summary.name, summary.version, summary.repository), "title");
+        buffer.insert(&mut iter, &format!("{}\n\n", summary.description));
+
+        if !summary.homepages.is_empty() {
+            insert_tagged(&mut iter, "Homepage: ", "heading");
+            for homepage in &summary.homepages {
+                link(&mut iter, homepage);
+            }
+            buffer.insert(&mut iter, "\n");
+        }
+        for &(label, value) in [("License: ", &summary.license), ("Slot: ", &summary.slot), ("Keywords: ", &summary.keywords)].iter() {
+            insert_tagged(&mut iter, label, "heading");
+            buffer.insert(&mut iter, &format!("{}\n", value));
+        }
+
+        let metadata = &summary.metadata;
+        if let Some(ref bugs_to) = metadata.bugs_to {
+            insert_tagged(&mut iter, "Bug tracker: ", "heading");
+            if bugs_to.contains("://") {
+                link(&mut iter, bugs_to);
+                buffer.insert(&mut iter, "\n");
+            } else {
+                buffer.insert(&mut iter, &format!("{}\n", bugs_to));
+            }
+        }
+        if !metadata.remote_ids.is_empty() {
+            insert_tagged(&mut iter, "\nUpstream\n", "heading");
+            for (kind, id) in &metadata.remote_ids {
+                buffer.insert(&mut iter, &format!("{}: {}\n", kind, id));
+            }
+        }
+        if !metadata.maintainers.is_empty() {
+            insert_tagged(&mut iter, "\nMaintainers\n", "heading");
+            for maintainer in &metadata.maintainers {
+                let mut line = match maintainer.name {
+                    Some(ref name) => format!("{} <{}>", name, maintainer.email),
+                    None => maintainer.email.clone(),
+                };
+                if maintainer.kind == "project" {
+                    line.push_str(" (project)");
+                }
+                if let Some(ref description) = maintainer.description {
+                    line.push_str(&format!(" - {}", description));
+                }
+                buffer.insert(&mut iter, &format!("{}\n", line));
+            }
+        }
+        if !metadata.long_description.is_empty() {
+            insert_tagged(&mut iter, "\nDescription\n", "heading");
+            buffer.insert(&mut iter, &format!("{}\n", metadata.long_description));
+        }
+        if !metadata.use_flags.is_empty() {
+            insert_tagged(&mut iter, "\nLocal USE flags\n", "heading");
+            for (flag, description) in &metadata.use_flags {
+                insert_tagged(&mut iter, flag, "heading");
+                buffer.insert(&mut iter, &format!(": {}\n", description));
+            }
         }
     }
 
+    /// The view picked in the View combo box; Search Results has nothing to show until a search is made.
+    fn active_view(combo_box: &gtk::ComboBoxText, current_search: &RefCell<Option<backend::Search>>) -> Option<View> {
+        match &*combo_box.get_active_text()? {
+            "All Packages" => Some(View::All),
+            "Installed Packages" => Some(View::Installed),
+            "Search Results" => current_search.borrow().clone().map(View::Search),
+            "Upgradeable Packages" => Some(View::Upgradeable),
+            "Deprecated Packages" => Some(View::Deprecated),
+            "Sets" => Some(View::Sets),
+            _ => None,
+        }
+    }
+
+    /// The repository picked in the repository combo box, or `None` when every repository is shown.
+    fn active_repository(repository_box: &gtk::ComboBoxText) -> Option<String> {
+        match repository_box.get_active() {
+            0 => None,
+            _ => repository_box.get_active_text(),
+        }
+    }
+
+    /// The selected package along with its recommended version, or the selected version when a package has been
+    /// expanded. Rows of the Sets view carry the category and name as `category/name`, everywhere else the category is
+    /// the one selected on the left.
+    fn selected_package(tree_view_category: &gtk::TreeView, tree_view_pkgs: &gtk::TreeView, sets: bool) -> Option<Package> {
+        let (tree_model_pkg, tree_iter_pkg) = tree_view_pkgs.get_selection().get_selected()?;
+        let repository = tree_model_pkg.get_value(&tree_iter_pkg, 4).get::<String>().unwrap_or_default();
+        let (selected, version) = match tree_model_pkg.iter_parent(&tree_iter_pkg) {
+            Some(tree_iter_parent) => (tree_model_pkg.get_value(&tree_iter_parent, 0).get::<String>()?,
+                                       tree_model_pkg.get_value(&tree_iter_pkg, 0).get::<String>()?),
+            None => (tree_model_pkg.get_value(&tree_iter_pkg, 0).get::<String>()?,
+                     tree_model_pkg.get_value(&tree_iter_pkg, 2).get::<String>().unwrap_or_default()),
+        };
+        let (category, name) = if sets {
+            let atom = selected.parse::<Atom>().ok()?;
+            (atom.category, atom.name)
+        } else {
+            let (tree_model_category, tree_iter_category) = tree_view_category.get_selection().get_selected()?;
+            (tree_model_category.get_value(&tree_iter_category, 0).get::<String>()?, selected)
+        };
+        Some(Package { category, name, version, repository })
+    }
+
+    let column_category = make_tree_view_column("Categories", 0);
+    let column_pkg_num = make_tree_view_column("# Pkgs", 1);
+
+    let model_category = gtk::ListStore::new(&[gtk::Type::String, gtk::Type::U64]);
+
+    load_category_model(&model_category, &statusbar, &db, View::All, None);
+
     let tree_view_category = gtk::TreeView::new_with_model(&model_category);
     tree_view_category.append_column(&column_category);
     tree_view_category.append_column(&column_pkg_num);
@@ -223,13 +668,23 @@ fn main() {
     let column_installed = make_tree_view_column("Installed", 1);
     let column_recommended = make_tree_view_column("Recommended", 2);
     let column_description = make_tree_view_column("Description", 3);
+    let column_repository = make_tree_view_column("Repository", 4);
+    let column_slot = make_tree_view_column("Slot", 5);
+    let column_keyword = make_tree_view_column("Keyword", 6);
+    let column_mask_status = make_tree_view_column("Mask status", 7);
 
-    let model_pkg_list = gtk::ListStore::new(&[gtk::Type::String, gtk::Type::String, gtk::Type::String, gtk::Type::String, gtk::Type::String]);
+    // Packages expand into their versions, which fill in the repository, slot, keyword and mask status columns.
+    let model_pkg_list = gtk::TreeStore::new(&[gtk::Type::String, gtk::Type::String, gtk::Type::String, gtk::Type::String, gtk::Type::String,
+                                               gtk::Type::String, gtk::Type::String, gtk::Type::String]);
 
     let tree_view_pkgs = gtk::TreeView::new_with_model(&model_pkg_list);
     tree_view_pkgs.append_column(&column_packages);
     tree_view_pkgs.append_column(&column_installed);
     tree_view_pkgs.append_column(&column_recommended);
+    tree_view_pkgs.append_column(&column_repository);
+    tree_view_pkgs.append_column(&column_slot);
+    tree_view_pkgs.append_column(&column_keyword);
+    tree_view_pkgs.append_column(&column_mask_status);
     tree_view_pkgs.append_column(&column_description);
     tree_view_pkgs.set_visible(true);
     let scrollable_pkg = gtk::ScrolledWindow::new(None, None);
@@ -242,15 +697,39 @@ fn main() {
     paned_category_pkg.set_hexpand(true);
 
     let notebook = gtk::Notebook::new();
-    let notebook_labels = ["Summary", "Dependencies", "Installed files", "Ebuild", "USE flags"];
-    let notebook_buffers = Rc::new([gtk::TextBuffer::new(&gtk::TextTagTable::new()),
-                                    gtk::TextBuffer::new(&gtk::TextTagTable::new()),
-                                    gtk::TextBuffer::new(&gtk::TextTagTable::new()),
-                                    gtk::TextBuffer::new(&gtk::TextTagTable::new()),
-                                    gtk::TextBuffer::new(&gtk::TextTagTable::new())]);
-    for (&label, buffer) in notebook_labels.iter().zip(notebook_buffers.iter()) {
+    let pages = PackagePages {
+        summary: gtk::TextView::new_with_buffer(&gtk::TextBuffer::new(&gtk::TextTagTable::new())),
+        dependencies: gtk::TreeView::new_with_model(&gtk::TreeStore::new(&[gtk::Type::String, gtk::Type::String])),
+        installed_files: gtk::TreeView::new_with_model(&gtk::TreeStore::new(&[gtk::Type::String, gtk::Type::String, gtk::Type::String, gtk::Type::String,
+                                                                              gtk::Type::String])),
+        ebuild: gtk::TextView::new_with_buffer(&gtk::TextBuffer::new(&gtk::TextTagTable::new())),
+        use_flags: gtk::TreeView::new_with_model(&gtk::ListStore::new(&[gtk::Type::String, gtk::Type::String, gtk::Type::String, gtk::Type::String,
+                                                                        gtk::Type::String, gtk::Type::String])),
+    };
+    pages.summary.set_editable(false);
+    pages.summary.set_wrap_mode(gtk::WrapMode::Word);
+    if let Some(tag_table) = pages.summary.get_buffer().and_then(|buffer| buffer.get_tag_table()) {
+        let heading = gtk::TextTag::new(Some("heading"));
+        heading.set_property_weight(700);
+        tag_table.add(&heading);
+        let title = gtk::TextTag::new(Some("title"));
+        title.set_property_weight(700);
+        title.set_property_scale(1.4);
+        tag_table.add(&title);
+    }
+    pages.dependencies.append_column(&make_tree_view_column("Dependency", 0));
+    pages.dependencies.append_column(&make_tree_view_column("Status", 1));
+    for (column_number, &title) in ["File", "Type", "MD5", "Modified", "Symlink target"].iter().enumerate() {
+        pages.installed_files.append_column(&make_tree_view_column(title, column_number as i32));
+    }
+    for (column_number, &title) in ["USE flag", "Default", "State", "Installed", "Changed", "Description"].iter().enumerate() {
+        pages.use_flags.append_column(&make_tree_view_column(title, column_number as i32));
+    }
+    for &(label, ref view) in [("Summary", pages.summary.clone().upcast::<gtk::Widget>()), ("Dependencies", pages.dependencies.clone().upcast()),
+                               ("Installed files", pages.installed_files.clone().upcast()), ("Ebuild", pages.ebuild.clone().upcast()),
+                               ("USE flags", pages.use_flags.clone().upcast())].iter() {
         let scrolled_window = gtk::ScrolledWindow::new(None, None);
-        scrolled_window.add(&gtk::TextView::new_with_buffer(buffer));
+        scrolled_window.add(view);
         notebook.append_page(&scrolled_window, Some(&gtk::Label::new(label)));
     }
 
@@ -265,6 +744,7 @@ fn main() {
     vbox.add(&toolbar);
     vbox.add(&hbox1);
     vbox.add(&paned_everything);
+    vbox.add(&statusbar);
 
     let window = Window::new(WindowType::Toplevel);
     window.set_title("Portage GUI");
@@ -272,359 +752,192 @@ fn main() {
     window.add(&vbox);
     window.show_all();
 
-    {
-        let db_response = db_response.clone();
-        let db_request = db_request.clone();
-        let tree_view_pkgs = tree_view_pkgs.clone();
-        let model_category = model_category.clone();
-        combo_box.connect_changed(move |combo_box| {
-            tree_view_pkgs.get_selection().unselect_all();
-            if let Some(entry) = combo_box.get_active_text() {
-                let selection = match &*entry {
-                    "Installed Packages" =>
-                        "SELECT category, count(DISTINCT name) as pkg_count
-                         FROM installed_packages
-                         GROUP BY category",
-                    "All Packages" =>
-                        "SELECT category, count(DISTINCT name) as pkg_count
-                         FROM all_packages
-                         GROUP BY category",
-                    "Sets" =>
-                        "SELECT portage_set, count(DISTINCT category_and_name) as pkg_count
-                         FROM portage_sets
-                         GROUP BY portage_set",
-                    _ => return,
-                };
-
-                model_category.clear();
-                db_request.send(Request::ModelCategory(selection.into()));
+    repositories_item.connect_activate(move |_| show_repositories(&repositories));
 
-                let model_category = model_category.clone();
-                let db_response = db_response.clone();
-                gtk::timeout_add(100, move || {
-
-                    match db_response.try_recv() {
-                        Ok(Response::ModelCategory(rows)) => {
-                            for row in rows {
-                                model_category.insert_with_values(None, &[0, 1], &[&row.0, &row.1]);
-                            }
-                            Continue(false)
-                        }
-                        Ok(_) => {
-                            eprintln!("invalid response");
-                            Continue(false)
-                        }
-                        Err(TryRecvError::Empty) => Continue(true),
-                        Err(why) => {
-                            eprintln!("failed to receive: {}", why);
-                            Continue(false)
-                        }
+    {
+        let load_categories: Rc<dyn Fn()> = {
+            let db = db.clone();
+            let statusbar = statusbar.clone();
+            let combo_box = combo_box.clone();
+            let repository_box = repository_box.clone();
+            let tree_view_pkgs = tree_view_pkgs.clone();
+            let model_category = model_category.clone();
+            let current_search = current_search.clone();
+            Rc::new(move || {
+                tree_view_pkgs.get_selection().unselect_all();
+                match active_view(&combo_box, &current_search) {
+                    Some(view) => load_category_model(&model_category, &statusbar, &db, view, active_repository(&repository_box)),
+                    None => {
+                        db.cancel(Lane::Categories);
+                        model_category.clear();
                     }
-                });
-            }
-        });
+                }
+            })
+        };
+
+        {
+            let load_categories = load_categories.clone();
+            combo_box.connect_changed(move |_| load_categories());
+        }
+        repository_box.connect_changed(move |_| load_categories());
     }
 
-    {
-        let db_response = db_response.clone();
-        let db_request = db_request.clone();
+    let load_packages: Rc<dyn Fn()> = {
+        let db = db.clone();
+        let statusbar = statusbar.clone();
         let combo_box = combo_box.clone();
+        let repository_box = repository_box.clone();
+        let tree_view_category = tree_view_category.clone();
         let tree_view_pkgs = tree_view_pkgs.clone();
         let model_pkg_list = model_pkg_list.clone();
-        tree_view_category.get_selection().connect_changed(move |selected_category| {
+        let current_search = current_search.clone();
+        Rc::new(move || {
+            let selected_category = tree_view_category.get_selection();
             model_pkg_list.clear();
+            db.cancel(Lane::Packages);
             tree_view_pkgs.get_selection().unselect_all();
             selected_category.set_mode(gtk::SelectionMode::Single);
 
             if let Some((tree_model_category, tree_iter_category)) = selected_category.get_selected() {
                 if let Some(selected) = tree_model_category.get_value(&tree_iter_category, 0).get::<String>() {
-                    let entry = combo_box.get_active_text().unwrap_or("".to_string());
-                    let selection = match &*entry {
-                        "Installed Packages" => format!(r#"SELECT installed_packages.name AS package_name,
-                                                           IFNULL(installed_packages.version, "") AS installed_version,
-                                                           IFNULL(recommended_packages.version, "Not available") AS recommended_version,
-                                                           all_packages.description AS description
-                                                           FROM installed_packages
-                                                           LEFT JOIN all_packages
-                                                           ON installed_packages.category = all_packages.category
-                                                           AND installed_packages.name = all_packages.name
-                                                           LEFT JOIN recommended_packages
-                                                           ON all_packages.category = recommended_packages.category
-                                                           AND all_packages.name = recommended_packages.name
-                                                           WHERE installed_packages.category LIKE '{}'
-                                                           GROUP BY package_name
-                                                           ORDER BY installed_packages.category ASC"#,
-                                                           selected),
-
-                        "All Packages" => format!(r#"SELECT all_packages.name AS package_name,
-                                                     IFNULL(installed_packages.version, "") AS installed_version,
-                                                     IFNULL(recommended_packages.version, "Not available") AS recommended_version,
-                                                     all_packages.description AS description
-                                                     FROM all_packages
-                                                     LEFT JOIN installed_packages
-                                                     ON all_packages.category = installed_packages.category
-                                                     AND all_packages.name = installed_packages.name
-                                                     LEFT JOIN recommended_packages
-                                                     ON all_packages.category = recommended_packages.category
-                                                     AND all_packages.name = recommended_packages.name
-                                                     WHERE all_packages.category LIKE '{}'
-                                                     GROUP BY package_name
-                                                     ORDER BY all_packages.category ASC"#,
-                                                     selected),
-
-                        "Sets" => format!(r#"SELECT portage_sets.category_and_name AS category_and_name,
-                                             IFNULL(installed_packages.version, "") AS installed_version,
-                                             IFNULL(recommended_packages.version, "Not available") AS recommended_version,
-                                             all_packages.description AS description
-                                             FROM portage_sets
-                                             LEFT JOIN all_packages
-                                             ON portage_sets.category = all_packages.category
-                                             AND portage_sets.name = all_packages.name
-                                             LEFT JOIN installed_packages
-                                             ON portage_sets.category = installed_packages.category
-                                             AND portage_sets.name = installed_packages.name
-                                             LEFT JOIN recommended_packages
-                                             ON portage_sets.category = recommended_packages.category
-                                             AND portage_sets.name = recommended_packages.name
-                                             WHERE portage_sets.portage_set LIKE '{}'
-                                             GROUP BY category_and_name
-                                             ORDER BY portage_sets.portage_set ASC"#,
-                                             selected),
-
-                        _ => format!(r#"SELECT all_packages.name AS package_name,
-                                        IFNULL(installed_packages.version, "") AS installed_version,
-                                        IFNULL(recommended_packages.version, "Not available") AS recommended_version,
-                                        all_packages.description AS description
-                                        FROM all_packages
-                                        LEFT JOIN installed_packages
-                                        ON all_packages.category = installed_packages.category
-                                        AND all_packages.name = installed_packages.name
-                                        LEFT JOIN recommended_packages
-                                        ON all_packages.category = recommended_packages.category
-                                        AND all_packages.name = recommended_packages.name
-                                        WHERE all_packages.category LIKE '{}'
-                                        GROUP BY package_name
-                                        ORDER BY all_packages.category ASC"#,
-                                        selected),
+                    let view = match active_view(&combo_box, &current_search) {
+                        Some(view) => view,
+                        None => return,
                     };
 
-                    db_request.send(Request::Other(selection));
-
+                    // Sets mix packages of every category, so their rows are labelled with it.
+                    let sets = match view { View::Sets => true, _ => false };
                     let model_pkg_list = model_pkg_list.clone();
-                    let db_response = db_response.clone();
-                    gtk::timeout_add(100, move || {
-                        match db_response.try_recv() {
-                            Ok(Response::Other(pkg_rows)) => {
-                                for row in pkg_rows {
-                                    model_pkg_list.insert_with_values(None, &[0, 1, 2, 3], &[&row.0, &row.1, &row.2, &row.3]);
+                    let statusbar = statusbar.clone();
+                    let request = Request::Packages(view, active_repository(&repository_box), selected);
+                    db.send(Lane::Packages, request, move |response| match response {
+                        Response::Packages(result) => {
+                            for row in report(&statusbar, result).unwrap_or_default() {
+                                let label = if sets { format!("{}/{}", row.category, row.name) } else { row.name.clone() };
+                                let package = model_pkg_list.insert_with_values(None, None, &[0, 1, 2, 3, 4], &[&label, &row.installed_version, &row.recommended_version,
+                                                                                                               &row.description, &row.repository]);
+                                for version in &row.versions {
+                                    model_pkg_list.insert_with_values(Some(&package), None, &[0, 1, 4, 5, 6, 7],
+                                                                      &[&version.version, &if version.installed { "Installed" } else { "" }, &version.repository,
+                                                                        &version.slot, &version.arch_keyword, &version.mask_status()]);
                                 }
-                                Continue(false)
-                            },
-                            Ok(_) => {
-                                eprintln!("invalid response");
-                                Continue(false)
-                            }
-                            Err(TryRecvError::Empty) => Continue(true),
-                            Err(why) => {
-                                eprintln!("error when receiving: {}", why);
-                                Continue(false)
                             }
                         }
+                        _ => eprintln!("invalid response"),
                     });
                 }
             }
+        })
+    };
+
+    {
+        let load_packages = load_packages.clone();
+        tree_view_category.get_selection().connect_changed(move |_| load_packages());
+    }
+
+    {
+        // The package list is loaded again after an emerge, so that installed versions stay current.
+        let db = db.clone();
+        let (vdb_changes, vdb_change_receiver) = channel();
+        thread::spawn(move || vdb::watch(watched_root, vdb_changes));
+        gtk::timeout_add(1000, move || {
+            if vdb_change_receiver.try_iter().count() > 0 {
+                let load_packages = load_packages.clone();
+                db.send(Lane::Refresh, Request::RefreshInstalled, move |response| match response {
+                    Response::RefreshInstalled(true) => load_packages(),
+                    Response::RefreshInstalled(false) => (),
+                    _ => eprintln!("invalid response"),
+                });
+            }
+            Continue(true)
         });
     }
 
     {
-        let db_response = db_response.clone();
-        let db_request = db_request.clone();
+        let db = db.clone();
+        let statusbar = statusbar.clone();
         let combo_box = combo_box.clone();
         let notebook = notebook.clone();
-        let notebook_buffers = notebook_buffers.clone();
+        let pages = pages.clone();
+        let tree_view_category = tree_view_category.clone();
+        let tree_view_pkgs = tree_view_pkgs.clone();
         tree_view_pkgs.get_selection().connect_changed(move |selected_pkg| {
             selected_pkg.set_mode(gtk::SelectionMode::Single);
 
-            if let Some((tree_model_pkg, tree_iter_pkg)) = selected_pkg.get_selected() {
-                if let Some(selected) = tree_model_pkg.get_value(&tree_iter_pkg, 0).get::<String>() {
-                    let entry = combo_box.get_active_text().unwrap_or("".to_string());
-                    if let Some(current_page) = notebook.get_current_page() {
-                        let query = match current_page {
-                            page if page == 2 => {
-                                Request::FileList(if entry == "Sets" {
-                                    let split: Vec<&str> = selected.split('/').collect();
-                                    let package = match split.get(1) {
-                                        Some(a) => *a,
-                                        None => return,
-                                    };
-                                    package.into()
-                                }
-                                else { selected })
-                            }
-                            page if page == 3 => {
-                                Request::Ebuilds(if entry == "Sets" {
-                                    let split: Vec<&str> = selected.split('/').collect();
-                                    format!("SELECT ebuild_path
-                                             FROM ebuilds
-                                             WHERE ebuilds.name = '{}'",
-                                                 match split.get(1) {
-                                                     Some(a) => a,
-                                                     None => return,
-                                                 }
-                                           )
-                                }
-                                else {
-                                    format!("SELECT ebuild_path
-                                             FROM ebuilds
-                                             WHERE ebuilds.name = '{}'", selected)
-                                })
-                            }
-                            _ => return,
-                        };
-
-                        db_request.send(query);
-
-                        let notebook_buffers = notebook_buffers.clone();
-                        let db_response = db_response.clone();
-                        gtk::timeout_add(100, move || {
-                            match db_response.try_recv() {
-                                Ok(Response::StringQuery(response)) => {
-                                    notebook_buffers[current_page as usize].set_text(&response);
-                                    Continue(false)
-                                },
-                                Ok(_) => {
-                                    eprintln!("invalid response");
-                                    Continue(false)
-                                }
-                                Err(TryRecvError::Empty) => Continue(true),
-                                Err(why) => {
-                                    eprintln!("error when receiving: {}", why);
-                                    Continue(false)
-                                }
-                            }
-                        });
-                    }
+            let sets = combo_box.get_active_text().map_or(false, |entry| entry == "Sets");
+            match (selected_package(&tree_view_category, &tree_view_pkgs, sets), notebook.get_current_page()) {
+                (Some(package), Some(current_page)) => {
+                    load_package_page(&pages, &statusbar, &db, current_page, package);
                 }
+                _ => db.cancel(Lane::Details),
             }
         });
     }
 
     {
-        let db_response = db_response.clone();
-        let db_request = db_request.clone();
+        let db = db.clone();
+        let statusbar = statusbar.clone();
+        let combo_box = combo_box.clone();
         notebook.connect_switch_page(move |_, _, current_page| {
-            let package_selection = tree_view_pkgs.get_selection();
-            package_selection.set_mode(gtk::SelectionMode::Single);
-
-            if let Some((tree_model_pkg, tree_iter_pkg)) = package_selection.get_selected() {
-                if let Some(selected) = tree_model_pkg.get_value(&tree_iter_pkg, 0).get::<String>() {
-                    let entry = combo_box.get_active_text().unwrap_or("".to_string());
-
-                    let query = match current_page {
-                        page if page == 2 => {
-                            Request::FileList(if entry == "Sets" {
-                                let split: Vec<&str> = selected.split('/').collect();
-                                let package = match split.get(1) {
-                                    Some(a) => *a,
-                                    None => return,
-                                };
-                                package.into()
-                            }
-                            else { selected })
-                        }
-                        page if page == 3 => {
-                            Request::Ebuilds(if entry == "Sets" {
-                                let split: Vec<&str> = selected.split('/').collect();
-                                format!("SELECT ebuild_path
-                                         FROM ebuilds
-                                         WHERE ebuilds.name = '{}'",
-                                             match split.get(1) {
-                                                 Some(a) => a,
-                                                 None => return,
-                                             }
-                                       )
-                            }
-                            else {
-                                format!("SELECT ebuild_path
-                                         FROM ebuilds
-                                         WHERE ebuilds.name = '{}'", selected)
-                            })
-                        }
-                        _ => return,
-                    };
+            tree_view_pkgs.get_selection().set_mode(gtk::SelectionMode::Single);
 
-                    db_request.send(query);
-                    let db_response = db_response.clone();
-                    let notebook_buffers = notebook_buffers.clone();
-                    gtk::timeout_add(100, move || {
-                        match db_response.try_recv() {
-                            Ok(Response::StringQuery(response)) => {
-                                notebook_buffers[current_page as usize].set_text(&response);
-                                Continue(false)
-                            },
-                            Ok(_) => {
-                                eprintln!("invalid response");
-                                Continue(false)
-                            }
-                            Err(TryRecvError::Empty) => Continue(true),
-                            Err(why) => {
-                                eprintln!("error when receiving: {}", why);
-                                Continue(false)
-                            }
-                        }
-                    });
+            let sets = combo_box.get_active_text().map_or(false, |entry| entry == "Sets");
+            match selected_package(&tree_view_category, &tree_view_pkgs, sets) {
+                Some(package) => {
+                    load_package_page(&pages, &statusbar, &db, current_page, package);
                 }
+                None => db.cancel(Lane::Details),
             }
         });
     }
 
     {
-        let db_response = db_response.clone();
-        let db_request = db_request.clone();
-        search_entry.connect_activate(move |search_entry| {
+        let run_search: Rc<dyn Fn()> = {
             let search_entry = search_entry.clone();
-            model_pkg_list.clear();
-
-            if let Some(search) = search_entry.get_text() {
-                let query = format!(r#"SELECT all_packages.name AS package_name,
-                                       IFNULL(installed_packages.version, "") AS installed_version,
-                                       IFNULL(recommended_packages.version, "Not available") AS recommended_version,
-                                       all_packages.description AS description
-                                       FROM all_packages
-                                       LEFT JOIN installed_packages
-                                       ON all_packages.category = installed_packages.category
-                                       AND all_packages.name = installed_packages.name
-                                       LEFT JOIN recommended_packages
-                                       ON all_packages.category = recommended_packages.category
-                                       AND all_packages.name = recommended_packages.name
-                                       WHERE all_packages.name LIKE '%{}%'
-                                       GROUP BY package_name
-                                       ORDER BY all_packages.category ASC"#,
-                                       search);
-                db_request.send(Request::Other(query));
-            }
+            let search_scope_box = search_scope_box.clone();
+            let search_installed_only = search_installed_only.clone();
+            let combo_box = combo_box.clone();
+            let repository_box = repository_box.clone();
+            let current_search = current_search.clone();
+            Rc::new(move || {
+                let text = match search_entry.get_text() {
+                    Some(ref text) if !text.trim().is_empty() => text.clone(),
+                    _ => return,
+                };
+                let scope = match search_scope_box.get_active() {
+                    1 => backend::SearchScope::Descriptions,
+                    2 => backend::SearchScope::NamesAndDescriptions,
+                    _ => backend::SearchScope::Names,
+                };
+                let search = backend::Search { text, scope, installed_only: search_installed_only.get_active() };
+                *current_search.borrow_mut() = Some(search.clone());
+
+                model_pkg_list.clear();
+                if combo_box.get_active_text().map_or(false, |entry| entry == "Search Results") {
+                    load_category_model(&model_category, &statusbar, &db, View::Search(search), active_repository(&repository_box));
+                } else {
+                    combo_box.set_active(2); // switching to "Search Results" loads the categories
+                }
+            })
+        };
 
-            let model_pkg_list = model_pkg_list.clone();
-            let db_response = db_response.clone();
-            gtk::timeout_add(100, move || {
-                match db_response.try_recv() {
-                    Ok(Response::Other(pkg_rows)) => {
-                        for row in pkg_rows {
-                            model_pkg_list.insert_with_values(None, &[0, 1, 2, 3], &[&row.0, &row.1, &row.2, &row.3]);
-                        }
-                        Continue(false)
-                    },
-                    Ok(_) => {
-                        eprintln!("invalid response");
-                        Continue(false)
-                    }
-                    Err(TryRecvError::Empty) => Continue(true),
-                    Err(why) => {
-                        eprintln!("error when receiving: {}", why);
-                        Continue(false)
-                    }
+        {
+            let run_search = run_search.clone();
+            search_entry.connect_activate(move |_| run_search());
+        }
+        {
+            let run_search = run_search.clone();
+            let current_search = current_search.clone();
+            search_scope_box.connect_changed(move |_| {
+                if current_search.borrow().is_some() {
+                    run_search();
                 }
             });
+        }
+        search_installed_only.connect_toggled(move |_| {
+            if current_search.borrow().is_some() {
+                run_search();
+            }
         });
     }
 
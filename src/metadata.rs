@@ -0,0 +1,321 @@
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// The parts of a package's `metadata.xml` that portix shows or indexes.
+#[derive(Default)]
+pub struct Metadata {
+    pub long_description: String,
+    pub maintainers: Vec<Maintainer>,
+    /// Upstream identifiers such as `("github", "rust-lang/rust")`.
+    pub remote_ids: Vec<(String, String)>,
+    pub bugs_to: Option<String>,
+    /// Descriptions of the USE flags specific to the package.
+    pub use_flags: Vec<(String, String)>,
+}
+
+/// A person or project listed in `<maintainer>`.
+pub struct Maintainer {
+    /// Either `person` or `project`.
+    pub kind: String,
+    pub email: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Reads `metadata.xml` from a package directory, returning `None` if it is missing or malformed.
+pub fn read_metadata(package_path: &Path) -> Option<Metadata> {
+    let mut xml = String::new();
+    fs::File::open(package_path.join("metadata.xml"))
+        .and_then(|mut file| file.read_to_string(&mut xml))
+        .ok()?;
+    let root = parse_xml(&xml)?;
+
+    let mut metadata = Metadata::default();
+    for element in root.elements("longdescription") {
+        match element.attribute("lang") {
+            None | Some("en") => {
+                metadata.long_description = element.text();
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    for element in root.elements("maintainer") {
+        let text_of = |name| element.elements(name).next().map(|child| child.text());
+        metadata.maintainers.push(Maintainer {
+            kind: element.attribute("type").unwrap_or("person").to_owned(),
+            email: text_of("email").unwrap_or_default(),
+            name: text_of("name"),
+            description: text_of("description"),
+        });
+    }
+
+    for upstream in root.elements("upstream") {
+        for element in upstream.elements("remote-id") {
+            metadata.remote_ids.push((element.attribute("type").unwrap_or("").to_owned(), element.text()));
+        }
+        if let Some(element) = upstream.elements("bugs-to").next() {
+            metadata.bugs_to = Some(element.text());
+        }
+    }
+
+    for use_element in root.elements("use") {
+        match use_element.attribute("lang") {
+            None | Some("en") => (),
+            _ => continue,
+        }
+        for element in use_element.elements("flag") {
+            if let Some(flag) = element.attribute("name") {
+                metadata.use_flags.push((flag.to_owned(), element.text()));
+            }
+        }
+    }
+    Some(metadata)
+}
+
+/// An XML element with its attributes and child nodes.
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|attribute| attribute.0 == name).map(|attribute| &*attribute.1)
+    }
+
+    /// Direct child elements named `name`.
+    pub fn elements<'a>(&'a self, name: &'a str) -> Box<dyn Iterator<Item = &'a Element> + 'a> {
+        Box::new(self.children.iter().filter_map(move |child| match *child {
+            Node::Element(ref element) if element.name == name => Some(element),
+            _ => None,
+        }))
+    }
+
+    /// All text inside the element, including that of nested markup, with runs of whitespace collapsed.
+    pub fn text(&self) -> String {
+        fn collect(element: &Element, text: &mut String) {
+            for child in &element.children {
+                match *child {
+                    Node::Element(ref element) => collect(element, text),
+                    Node::Text(ref content) => text.push_str(content),
+                }
+            }
+        }
+
+        let mut text = String::new();
+        collect(self, &mut text);
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Parses the root element of a document, which is all that is needed for `metadata.xml` files.
+///
+/// Declarations, doctypes and comments are skipped; namespaces and DTD entities are not supported.
+pub fn parse_xml(xml: &str) -> Option<Element> {
+    let mut parser = Parser { xml, position: 0 };
+    loop {
+        parser.skip_misc();
+        if parser.rest().starts_with('<') {
+            return parser.element();
+        } else if parser.rest().is_empty() {
+            return None;
+        }
+        parser.position += parser.rest().find('<').unwrap_or(parser.rest().len());
+    }
+}
+
+struct Parser<'a> {
+    xml: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.xml[self.position..]
+    }
+
+    fn skip_until(&mut self, terminator: &str) {
+        self.position += self.rest().find(terminator).map_or(self.rest().len(), |index| index + terminator.len());
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skips whitespace, `<?...?>` declarations, `<!DOCTYPE ...>` and comments.
+    fn skip_misc(&mut self) {
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("<?") {
+                self.skip_until("?>");
+            } else if self.rest().starts_with("<!--") {
+                self.skip_until("-->");
+            } else if self.rest().starts_with("<!") && !self.rest().starts_with("<![CDATA[") {
+                self.skip_until(">");
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let rest = self.rest();
+        let length = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/' || c == '=').unwrap_or(rest.len());
+        if length == 0 {
+            return None;
+        }
+        self.position += length;
+        Some(rest[..length].to_owned())
+    }
+
+    fn element(&mut self) -> Option<Element> {
+        self.position += 1; // the opening '<'
+        let name = self.name()?;
+        let mut attributes = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.position += 2;
+                return Some(Element { name, attributes, children: Vec::new() });
+            } else if self.rest().starts_with('>') {
+                self.position += 1;
+                break;
+            }
+
+            let attribute = self.name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return None;
+            }
+            self.position += 1;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => return None,
+            };
+            self.position += 1;
+            let length = self.rest().find(quote)?;
+            let value = decode_entities(&self.rest()[..length]);
+            self.position += length + 1;
+            attributes.push((attribute, value));
+        }
+
+        let mut children = Vec::new();
+        loop {
+            let rest = self.rest();
+            if rest.is_empty() {
+                return None;
+            } else if rest.starts_with("</") {
+                self.skip_until(">");
+                return Some(Element { name, attributes, children });
+            } else if rest.starts_with("<!--") {
+                self.skip_until("-->");
+            } else if rest.starts_with("<![CDATA[") {
+                let length = rest.find("]]>")?;
+                children.push(Node::Text(rest["<![CDATA[".len()..length].to_owned()));
+                self.position += length + 3;
+            } else if rest.starts_with('<') {
+                children.push(Node::Element(self.element()?));
+            } else {
+                let length = rest.find('<').unwrap_or(rest.len());
+                children.push(Node::Text(decode_entities(&rest[..length])));
+                self.position += length;
+            }
+        }
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let character = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(::std::char::from_u32),
+            _ => None,
+        };
+        match character {
+            Some(character) => decoded.push(character),
+            None => decoded.push_str(&rest[..end + 1]),
+        }
+        rest = &rest[end + 1..];
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures;
+
+    fn fixture_metadata() -> Metadata {
+        read_metadata(&fixtures::repo().join("app-misc/hello")).expect("failed to read metadata.xml")
+    }
+
+    #[test]
+    fn reads_the_english_long_description() {
+        assert_eq!(fixture_metadata().long_description,
+                   "GNU Hello prints a friendly greeting. It serves as an example of <standard> GNU coding practices, as used by \
+                    sys-devel/autoconf.");
+    }
+
+    #[test]
+    fn reads_maintainers_with_and_without_names() {
+        let metadata = fixture_metadata();
+        let maintainers: Vec<(&str, &str, Option<&str>, Option<&str>)> = metadata.maintainers.iter()
+            .map(|maintainer| (&*maintainer.kind, &*maintainer.email, maintainer.name.as_ref().map(String::as_str),
+                               maintainer.description.as_ref().map(String::as_str)))
+            .collect();
+        assert_eq!(maintainers, vec![
+            ("person", "jane@example.org", Some("Jane Doe"), Some("Primary maintainer")),
+            ("project", "base-system@gentoo.org", Some("Gentoo Base System"), None),
+            ("person", "proxy-maint@gentoo.org", None, None),
+        ]);
+    }
+
+    #[test]
+    fn reads_upstream_and_english_use_flags() {
+        let metadata = fixture_metadata();
+        assert_eq!(metadata.remote_ids, vec![("savannah".to_owned(), "hello".to_owned()), ("github".to_owned(), "gnu/hello".to_owned())]);
+        assert_eq!(metadata.bugs_to.as_ref().map(String::as_str), Some("mailto:bug-hello@gnu.org"));
+        assert_eq!(metadata.use_flags, vec![
+            ("static".to_owned(), "Link the app-misc/hello binary statically".to_owned()),
+            ("nls".to_owned(), "Translate messages with <gettext> & friends".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn decodes_entities_and_keeps_cdata_as_written() {
+        let root = parse_xml("<a x='&quot;1&#x27;' y=\"&#60;\">&amp;&unknown;<![CDATA[&lt;b&gt;]]><b/>&gt;</a>").expect("failed to parse");
+        assert_eq!((root.attribute("x"), root.attribute("y")), (Some("\"1'"), Some("<")));
+        assert_eq!(root.text(), "&&unknown;&lt;b&gt;>");
+        assert_eq!(root.elements("b").count(), 1);
+
+        assert!(parse_xml("<a><b></a>").is_none());
+        assert!(parse_xml("<!-- nothing -->").is_none());
+    }
+}
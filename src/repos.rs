@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Portage's defaults, which `/etc/portage/repos.conf` builds upon.
+const DEFAULTS_PATH: &str = "usr/share/portage/config/repos.conf";
+const REPOS_CONF_PATH: &str = "etc/portage/repos.conf";
+
+/// A repository configured in `repos.conf`.
+#[derive(Clone)]
+pub struct Repository {
+    pub name: String,
+    pub location: PathBuf,
+    pub priority: i32,
+    pub masters: Vec<String>,
+    pub sync_type: Option<String>,
+    pub sync_uri: Option<String>,
+    pub auto_sync: bool,
+}
+
+/// Reads every repository configured under `config_root`, highest priority first.
+///
+/// Portage's own defaults are read before `etc/portage/repos.conf`, which may be a single file or a directory of
+/// files that are read in name order. Keys of the `DEFAULT` section apply to every repository that does not set them,
+/// and repositories without a `location` are left out.
+pub fn read_repos_conf<P: AsRef<Path>>(config_root: P) -> Vec<Repository> {
+    let config_root = config_root.as_ref();
+    let mut sections: Vec<(String, HashMap<String, String>)> = Vec::new();
+    for path in conf_files(&config_root.join(DEFAULTS_PATH)).into_iter().chain(conf_files(&config_root.join(REPOS_CONF_PATH))) {
+        let mut text = String::new();
+        if let Err(why) = fs::File::open(&path).and_then(|mut file| file.read_to_string(&mut text)) {
+            eprintln!("failed to read {}: {}", path.display(), why);
+            continue;
+        }
+        parse_sections(&text, &mut sections);
+    }
+
+    let defaults = sections.iter()
+        .find(|(name, _)| name == "DEFAULT")
+        .map(|(_, keys)| keys.clone())
+        .unwrap_or_default();
+    let mut repositories: Vec<Repository> = sections.into_iter()
+        .filter(|(name, _)| name != "DEFAULT")
+        .filter_map(|(name, keys)| {
+            // Empty values count as unset, as `sync-uri =` is a common way of disabling syncing.
+            let get = |key: &str| match keys.get(key).or_else(|| defaults.get(key)) {
+                Some(value) if !value.is_empty() => Some(value.clone()),
+                _ => None,
+            };
+            let location = get("location")?;
+            Some(Repository {
+                priority: get("priority").and_then(|priority| priority.parse().ok()).unwrap_or(0),
+                masters: get("masters").map_or_else(Vec::new, |masters| masters.split_whitespace().map(|master| master.to_owned()).collect()),
+                sync_type: get("sync-type"),
+                sync_uri: get("sync-uri"),
+                auto_sync: get("auto-sync").map_or(true, |auto_sync| parse_bool(&auto_sync)),
+                location: PathBuf::from(location),
+                name,
+            })
+        })
+        .collect();
+    repositories.sort_by_key(|repo| -repo.priority);
+    repositories
+}
+
+/// The files making up a configuration path, which may be a directory of files or a single file.
+pub fn conf_files(path: &Path) -> Vec<PathBuf> {
+    if !path.is_dir() {
+        return if path.exists() { vec![path.to_owned()] } else { Vec::new() };
+    }
+
+    let mut files: Vec<PathBuf> = match fs::read_dir(path) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+        Err(why) => {
+            eprintln!("failed to read {}: {}", path.display(), why);
+            return Vec::new();
+        }
+    };
+    // Portage skips hidden files and editor backups.
+    files.retain(|file| {
+        let file_name = file.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        file.is_file() && !file_name.starts_with('.') && !file_name.ends_with('~')
+    });
+    files.sort();
+    files
+}
+
+/// Adds the `[section]`s and `key = value` pairs of an INI-style file to `sections`, merging into sections of the
+/// same name that earlier files declared. Comments start with `#` or `;`.
+fn parse_sections(text: &str, sections: &mut Vec<(String, HashMap<String, String>)>) {
+    let mut current = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1].trim();
+            current = Some(match sections.iter().position(|(section, _)| section == name) {
+                Some(index) => index,
+                None => {
+                    sections.push((name.to_owned(), HashMap::new()));
+                    sections.len() - 1
+                }
+            });
+        } else if let (Some(index), Some(equals)) = (current, line.find('=')) {
+            let key = line[..equals].trim().to_lowercase();
+            let value = line[equals + 1..].trim().to_owned();
+            sections[index].1.insert(key, value);
+        }
+    }
+}
+
+/// Reads a boolean the way Python's configparser does, which is what portage uses.
+fn parse_bool(value: &str) -> bool {
+    match &*value.to_lowercase() {
+        "no" | "false" | "off" | "0" => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures;
+
+    fn repository<'a>(repos: &'a [Repository], name: &str) -> &'a Repository {
+        repos.iter().find(|repo| repo.name == name).unwrap_or_else(|| panic!("{} is not configured", name))
+    }
+
+    #[test]
+    fn sorts_by_priority_and_drops_repositories_without_a_location() {
+        let repos = read_repos_conf(fixtures::root());
+        let order: Vec<(&str, i32)> = repos.iter().map(|repo| (repo.name.as_str(), repo.priority)).collect();
+        assert_eq!(order, vec![("local", 100), ("overlay", 0), ("gentoo", -1000)]);
+    }
+
+    #[test]
+    fn inherits_keys_from_the_default_section() {
+        let repos = read_repos_conf(fixtures::root());
+        let gentoo = repository(&repos, "gentoo");
+        assert_eq!(gentoo.location, Path::new("/var/db/repos/gentoo"));
+        assert_eq!(gentoo.sync_type.as_ref().map(String::as_str), Some("rsync"));
+        assert!(gentoo.auto_sync);
+
+        let overlay = repository(&repos, "overlay");
+        assert_eq!(overlay.sync_type.as_ref().map(String::as_str), Some("rsync"));
+        assert!(!overlay.auto_sync);
+
+        let local = repository(&repos, "local");
+        assert_eq!(local.sync_type.as_ref().map(String::as_str), Some("git"));
+        assert_eq!(local.masters, vec!["gentoo".to_owned()]);
+        assert!(!local.auto_sync);
+    }
+
+    #[test]
+    fn treats_empty_values_as_unset() {
+        let repos = read_repos_conf(fixtures::root());
+        assert_eq!(repository(&repos, "local").sync_uri, None);
+        assert_eq!(repository(&repos, "gentoo").sync_uri.as_ref().map(String::as_str), Some("rsync://rsync.gentoo.org/gentoo-portage"));
+    }
+
+    #[test]
+    fn reads_conf_files_in_name_order() {
+        let directory = fixtures::root().join(REPOS_CONF_PATH);
+        let names: Vec<String> = conf_files(&directory).iter()
+            .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["gentoo.conf", "local.conf", "overlay.conf"]);
+
+        assert_eq!(conf_files(&directory.join("local.conf")), vec![directory.join("local.conf")]);
+        assert!(conf_files(&directory.join("missing.conf")).is_empty());
+    }
+
+    #[test]
+    fn parses_booleans_like_configparser() {
+        for value in &["no", "False", "OFF", "0"] {
+            assert!(!parse_bool(value), "{} is true", value);
+        }
+        for value in &["yes", "True", "on", "1"] {
+            assert!(parse_bool(value), "{} is false", value);
+        }
+    }
+}
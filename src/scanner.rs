@@ -0,0 +1,375 @@
+extern crate rayon;
+
+use self::rayon::prelude::*;
+
+use atom::Atom;
+use version::{self, Version};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Top-level directories of a repository that never contain packages.
+const NON_CATEGORY_DIRS: &[&str] = &["eclass", "licenses", "metadata", "profiles", "scripts"];
+
+/// A single ebuild found while walking a repository tree.
+pub struct Ebuild {
+    pub category: String,
+    pub name: String,
+    pub version: Version,
+    pub repository: String,
+    pub slot: String,
+    pub keywords: String,
+    pub description: String,
+    pub homepage: String,
+    pub path: PathBuf,
+}
+
+/// Walks the `category/package/*.ebuild` layout of the repository named `repository` at `repo_path`.
+///
+/// `SLOT`, `KEYWORDS`, `DESCRIPTION` and `HOMEPAGE` are read from `metadata/md5-cache` when the repository ships one,
+/// otherwise they are picked out of the ebuild itself without sourcing it.
+pub fn scan_repository<P: AsRef<Path>>(repo_path: P, repository: &str) -> Vec<Ebuild> {
+    let repo_path = repo_path.as_ref();
+    let categories: Vec<_> = match fs::read_dir(repo_path) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+        Err(why) => {
+            eprintln!("failed to read repository {}: {}", repo_path.display(), why);
+            return Vec::new();
+        }
+    };
+
+    categories.par_iter()
+        .flat_map(|category_path| scan_category(repo_path, repository, category_path))
+        .collect()
+}
+
+fn scan_category(repo_path: &Path, repository: &str, category_path: &Path) -> Vec<Ebuild> {
+    let mut ebuilds = Vec::new();
+    let category = match category_path.file_name() {
+        Some(category) => category.to_string_lossy().into_owned(),
+        None => return ebuilds,
+    };
+    if !category_path.is_dir() || category.starts_with('.') || NON_CATEGORY_DIRS.contains(&&*category) {
+        return ebuilds;
+    }
+
+    let package_entries = match fs::read_dir(category_path) {
+        Ok(entries) => entries,
+        Err(_) => return ebuilds,
+    };
+    for package_entry in package_entries {
+        let package_path = package_entry.expect("intermittent IO error").path();
+        ebuilds.extend(scan_package_dir(repo_path, repository, &category, &package_path));
+    }
+    ebuilds
+}
+
+/// Reads the ebuilds of a single `category/name` directory of the repository at `repo_path`, which may no longer exist.
+pub fn scan_package(repo_path: &Path, repository: &str, category: &str, name: &str) -> Vec<Ebuild> {
+    scan_package_dir(repo_path, repository, category, &repo_path.join(category).join(name))
+}
+
+fn scan_package_dir(repo_path: &Path, repository: &str, category: &str, package_path: &Path) -> Vec<Ebuild> {
+    let mut ebuilds = Vec::new();
+    let package = match package_path.file_name() {
+        Some(package) => package.to_string_lossy().into_owned(),
+        None => return ebuilds,
+    };
+    if !package_path.is_dir() || package.starts_with('.') {
+        return ebuilds;
+    }
+
+    for file_entry in fs::read_dir(package_path).expect("package path does not exist") {
+        let file_path = file_entry.expect("intermittent IO error").path();
+        let version = {
+            let file_string = file_path.file_name().unwrap().to_string_lossy();
+            match version::from_ebuild_filename(&file_string) {
+                Some((name, version)) if name == package => version,
+                _ => continue,
+            }
+        };
+
+        let pf = format!("{}-{}", package, version);
+        let mut variables = read_variables(repo_path, category, &pf, &file_path);
+
+        ebuilds.push(Ebuild {
+            category: category.to_owned(),
+            name: package.clone(),
+            version,
+            repository: repository.to_owned(),
+            slot: variables.remove("SLOT").unwrap_or_else(|| "0".to_owned()),
+            keywords: variables.remove("KEYWORDS").unwrap_or_default(),
+            description: variables.remove("DESCRIPTION").unwrap_or_default(),
+            homepage: variables.remove("HOMEPAGE").unwrap_or_default(),
+            path: file_path,
+        });
+    }
+    ebuilds
+}
+
+/// The `(category, name)` of the package a repository-relative path belongs to, such as the one of
+/// `app-misc/hello/hello-1.0.ebuild` or of its `metadata/md5-cache/app-misc/hello-1.0` entry.
+pub fn package_for_path(path: &str) -> Option<(String, String)> {
+    let mut components = path.split('/');
+    match (components.next(), components.next(), components.next(), components.next()) {
+        (Some("metadata"), Some("md5-cache"), Some(category), Some(pf)) => {
+            version::split_pf(pf).map(|(name, _)| (category.to_owned(), name.to_owned()))
+        }
+        (Some(category), Some(name), Some(_), _) if !category.starts_with('.') && !NON_CATEGORY_DIRS.contains(&category) => {
+            Some((category.to_owned(), name.to_owned()))
+        }
+        _ => None,
+    }
+}
+
+/// The metadata variables of the ebuild at `ebuild_path`, read from its `metadata/md5-cache` entry when there is one and
+/// picked out of the ebuild itself otherwise.
+pub fn read_variables(repo_path: &Path, category: &str, pf: &str, ebuild_path: &Path) -> HashMap<String, String> {
+    read_cache_entry(repo_path, category, pf).unwrap_or_else(|| read_ebuild_variables(ebuild_path))
+}
+
+/// Parses the `metadata/md5-cache/<category>/<pf>` entry of a repository into its `KEY=VALUE` pairs.
+pub fn read_cache_entry(repo_path: &Path, category: &str, pf: &str) -> Option<HashMap<String, String>> {
+    let mut cache_text = String::new();
+    fs::File::open(repo_path.join("metadata/md5-cache").join(category).join(pf))
+        .and_then(|mut cache_file| cache_file.read_to_string(&mut cache_text))
+        .ok()?;
+
+    Some(cache_text.lines()
+        .filter_map(|line| {
+            let mut split = line.splitn(2, '=');
+            match (split.next(), split.next()) {
+                (Some(key), Some(value)) => Some((key.to_owned(), value.to_owned())),
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+/// The variables `read_ebuild_variables` looks for.
+const EBUILD_VARIABLES: &[&str] = &["DESCRIPTION", "HOMEPAGE", "SLOT", "KEYWORDS", "LICENSE", "IUSE", "DEPEND", "RDEPEND", "BDEPEND", "PDEPEND",
+                                    "IDEPEND"];
+
+/// Extracts plain top-level assignments of the `EBUILD_VARIABLES` from an ebuild, without sourcing it.
+///
+/// Quoted values may span multiple lines, `+=` appends, and references to variables read earlier in the ebuild, such
+/// as `RDEPEND="${DEPEND}"`, are expanded. Anything else that relies on bash, like eclass variables, is taken as written.
+fn read_ebuild_variables(ebuild_path: &Path) -> HashMap<String, String> {
+    let mut variables: HashMap<String, String> = HashMap::new();
+    let mut ebuild_text = String::new();
+    if fs::File::open(ebuild_path).and_then(|mut file| file.read_to_string(&mut ebuild_text)).is_err() {
+        return variables;
+    }
+
+    let mut lines = ebuild_text.lines();
+    while let Some(line) = lines.next() {
+        let key = match EBUILD_VARIABLES.iter().find(|key| line.starts_with(**key)) {
+            Some(key) => *key,
+            None => continue,
+        };
+        let (append, rest) = match &line[key.len()..] {
+            rest if rest.starts_with("+=") => (true, &rest[2..]),
+            rest if rest.starts_with('=') => (false, &rest[1..]),
+            _ => continue,
+        };
+
+        let mut value = rest.trim().to_owned();
+        let quote = if value.starts_with('"') || value.starts_with('\'') { Some(value.remove(0)) } else { None };
+        if let Some(quote) = quote {
+            loop {
+                if let Some(end) = closing_quote(&value, quote) {
+                    value.truncate(end);
+                    break;
+                }
+                match lines.next() {
+                    Some(line) => {
+                        value.push('\n');
+                        value.push_str(line.trim());
+                    }
+                    None => break,
+                }
+            }
+        }
+        let mut value = expand_variables(&value, &variables);
+        if quote == Some('"') {
+            value = unescape(&value);
+        }
+
+        if append {
+            let entry = variables.entry(key.to_owned()).or_default();
+            entry.push(' ');
+            entry.push_str(&value);
+        } else if !variables.contains_key(key) || key.ends_with("DEPEND") {
+            // Dependencies are commonly reassigned in terms of themselves, while a second DESCRIPTION is usually
+            // inside of a conditional that cannot be evaluated here.
+            variables.insert(key.to_owned(), value);
+        }
+    }
+    variables
+}
+
+/// The index of the quote ending a value that started with `quote`. Only double quoted values can escape it.
+fn closing_quote(value: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (index, character) in value.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if character == '\\' && quote == '"' {
+            escaped = true;
+        } else if character == quote {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Removes the backslashes bash would inside of double quotes, as in `\"quotes\"`.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut characters = value.chars().peekable();
+    while let Some(character) = characters.next() {
+        match (character, characters.peek()) {
+            ('\\', Some(&next)) if next == '"' || next == '\\' || next == '$' || next == '`' => {
+                unescaped.push(next);
+                characters.next();
+            }
+            _ => unescaped.push(character),
+        }
+    }
+    unescaped
+}
+
+/// Replaces `${KEY}` and `$KEY` references to already read variables.
+fn expand_variables(value: &str, variables: &HashMap<String, String>) -> String {
+    let mut expanded = value.to_owned();
+    for (key, replacement) in variables {
+        expanded = expanded.replace(&format!("${{{}}}", key), replacement);
+        expanded = expanded.replace(&format!("${}", key), replacement);
+    }
+    expanded
+}
+
+/// Reads the atoms listed in the repository's `profiles/package.mask` along with their text, skipping comments and
+/// blank lines.
+pub fn read_package_mask(repo_path: &Path) -> Vec<(String, Atom)> {
+    let mut mask_text = String::new();
+    if fs::File::open(repo_path.join("profiles/package.mask")).and_then(|mut file| file.read_to_string(&mut mask_text)).is_err() {
+        return Vec::new();
+    }
+
+    mask_text.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| line.parse::<Atom>().ok().map(|atom| (line.to_owned(), atom)))
+        .collect()
+}
+
+/// Reads the global USE flag descriptions of a repository from `profiles/use.desc`, along with those of USE_EXPAND
+/// flags from `profiles/desc/*.desc`, which are keyed by their full name such as `python_targets_python3_6`.
+pub fn read_use_descriptions(repo_path: &Path) -> HashMap<String, String> {
+    let mut descriptions = HashMap::new();
+    read_use_desc_file(&repo_path.join("profiles/use.desc"), |flag, description| {
+        descriptions.insert(flag.to_owned(), description.to_owned());
+    });
+
+    if let Ok(entries) = fs::read_dir(repo_path.join("profiles/desc")) {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let prefix = match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(extension)) if extension == "desc" => stem.to_string_lossy().to_lowercase(),
+                _ => continue,
+            };
+            read_use_desc_file(&path, |value, description| {
+                descriptions.insert(format!("{}_{}", prefix, value), description.to_owned());
+            });
+        }
+    }
+    descriptions
+}
+
+/// Reads the descriptions `profiles/use.local.desc` gives for the USE flags of `category/name`.
+pub fn read_local_use_descriptions(repo_path: &Path, category: &str, name: &str) -> HashMap<String, String> {
+    let package = format!("{}/{}:", category, name);
+    let mut descriptions = HashMap::new();
+    read_use_desc_file(&repo_path.join("profiles/use.local.desc"), |entry, description| {
+        if entry.starts_with(&package) {
+            descriptions.insert(entry[package.len()..].to_owned(), description.to_owned());
+        }
+    });
+    descriptions
+}
+
+/// Calls `found` with the key and description of every `key - description` line of a description file.
+fn read_use_desc_file<F: FnMut(&str, &str)>(path: &Path, mut found: F) {
+    let mut desc_text = String::new();
+    if fs::File::open(path).and_then(|mut file| file.read_to_string(&mut desc_text)).is_err() {
+        return;
+    }
+
+    for line in desc_text.lines().filter(|line| !line.starts_with('#')) {
+        if let Some(index) = line.find(" - ") {
+            found(line[..index].trim(), line[index + 3..].trim());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures;
+
+    fn find<'a>(ebuilds: &'a [Ebuild], name: &str, version: &str) -> &'a Ebuild {
+        ebuilds.iter()
+            .find(|ebuild| ebuild.name == name && ebuild.version.as_str() == version)
+            .unwrap_or_else(|| panic!("{}-{} was not scanned", name, version))
+    }
+
+    #[test]
+    fn scans_every_ebuild() {
+        let ebuilds = scan_repository(fixtures::repo(), "portix-test");
+        assert_eq!(ebuilds.len(), 3);
+        assert!(ebuilds.iter().all(|ebuild| ebuild.repository == "portix-test"));
+    }
+
+    #[test]
+    fn reads_variables_from_md5_cache() {
+        assert!(read_cache_entry(&fixtures::repo(), "app-misc", "hello-2.0_rc1").is_some());
+
+        let ebuilds = scan_repository(fixtures::repo(), "portix-test");
+        let hello = find(&ebuilds, "hello", "2.0_rc1");
+        assert_eq!(hello.category, "app-misc");
+        assert_eq!(hello.slot, "0");
+        assert_eq!(hello.keywords, "~amd64 ~x86");
+        assert_eq!(hello.description, r#"Prints a friendly greeting, with commas, and "quotes""#);
+    }
+
+    #[test]
+    fn falls_back_to_the_ebuild() {
+        assert!(read_cache_entry(&fixtures::repo(), "dev-libs", "foo-bar-1.2.3-r1").is_none());
+
+        let ebuilds = scan_repository(fixtures::repo(), "portix-test");
+        let foo_bar = find(&ebuilds, "foo-bar", "1.2.3-r1");
+        assert_eq!(foo_bar.category, "dev-libs");
+        assert_eq!(foo_bar.slot, "0/1.2");
+        assert_eq!(foo_bar.keywords, "amd64");
+        assert_eq!(foo_bar.description, "Library without a metadata cache entry");
+    }
+
+    #[test]
+    fn reads_escaped_quotes_from_ebuilds() {
+        let variables = read_ebuild_variables(&fixtures::repo().join("app-misc/hello/hello-1.0.ebuild"));
+        assert_eq!(variables["DESCRIPTION"], "Prints a friendly greeting, with commas, and \"quotes\"");
+        assert_eq!(variables["SLOT"], "0");
+    }
+
+    #[test]
+    fn maps_paths_to_packages() {
+        let hello = Some(("app-misc".to_owned(), "hello".to_owned()));
+        assert_eq!(package_for_path("app-misc/hello/hello-1.0.ebuild"), hello);
+        assert_eq!(package_for_path("metadata/md5-cache/app-misc/hello-1.0"), hello);
+        assert_eq!(package_for_path("profiles/package.mask"), None);
+        assert_eq!(package_for_path("app-misc/hello"), None);
+    }
+}
@@ -0,0 +1,213 @@
+extern crate rusqlite;
+
+use self::rusqlite::{Connection, NO_PARAMS};
+
+/// Each entry upgrades the schema by one version: `MIGRATIONS[0]` takes a database from `user_version` 0 to 1.
+///
+/// Existing entries must never change once released, so that any older cache can be brought forward step by step.
+const MIGRATIONS: &[&str] = &[
+    // 1: typed tables with keys, replacing the untyped tables that were created on every reload.
+    "DROP TABLE IF EXISTS all_packages;
+     DROP TABLE IF EXISTS recommended_packages;
+     DROP TABLE IF EXISTS installed_packages;
+     DROP TABLE IF EXISTS upgradeable_packages;
+     DROP TABLE IF EXISTS deprecated_packages;
+     DROP TABLE IF EXISTS portage_sets;
+     DROP TABLE IF EXISTS ebuilds;
+     DROP TABLE IF EXISTS repo_hashes;
+     DROP TABLE IF EXISTS packages_fts;
+
+     CREATE TABLE packages (
+     category TEXT NOT NULL,
+     name     TEXT NOT NULL,
+     PRIMARY KEY (category, name)
+     );
+
+     CREATE TABLE all_packages (
+     category    TEXT NOT NULL,
+     name        TEXT NOT NULL,
+     version     TEXT NOT NULL,
+     slot        TEXT NOT NULL,
+     keywords    TEXT NOT NULL,
+     description TEXT NOT NULL,
+     PRIMARY KEY (category, name, version),
+     FOREIGN KEY (category, name) REFERENCES packages (category, name) ON DELETE CASCADE
+     );
+
+     CREATE TABLE recommended_packages (
+     category TEXT NOT NULL,
+     name     TEXT NOT NULL,
+     version  TEXT NOT NULL,
+     PRIMARY KEY (category, name),
+     FOREIGN KEY (category, name, version) REFERENCES all_packages (category, name, version) ON DELETE CASCADE
+     );
+
+     CREATE TABLE ebuilds (
+     category    TEXT NOT NULL,
+     name        TEXT NOT NULL,
+     version     TEXT NOT NULL,
+     ebuild_path TEXT NOT NULL,
+     PRIMARY KEY (category, name, version),
+     FOREIGN KEY (category, name, version) REFERENCES all_packages (category, name, version) ON DELETE CASCADE
+     );
+
+     CREATE TABLE installed_packages (
+     category   TEXT NOT NULL,
+     name       TEXT NOT NULL,
+     version    TEXT NOT NULL,
+     slot       TEXT NOT NULL,
+     use_flags  TEXT NOT NULL,
+     iuse       TEXT NOT NULL,
+     keywords   TEXT NOT NULL,
+     repository TEXT NOT NULL,
+     build_time INTEGER NOT NULL,
+     size       INTEGER NOT NULL,
+     depend     TEXT NOT NULL,
+     rdepend    TEXT NOT NULL,
+     vdb_path   TEXT NOT NULL,
+     PRIMARY KEY (category, name, version)
+     );
+
+     CREATE TABLE upgradeable_packages (
+     category          TEXT NOT NULL,
+     name              TEXT NOT NULL,
+     slot              TEXT NOT NULL,
+     installed_version TEXT NOT NULL,
+     version           TEXT NOT NULL,
+     PRIMARY KEY (category, name, slot),
+     FOREIGN KEY (category, name, installed_version) REFERENCES installed_packages (category, name, version) ON DELETE CASCADE,
+     FOREIGN KEY (category, name, version) REFERENCES all_packages (category, name, version) ON DELETE CASCADE
+     );
+
+     CREATE TABLE deprecated_packages (
+     category TEXT NOT NULL,
+     name     TEXT NOT NULL,
+     version  TEXT NOT NULL,
+     reason   TEXT NOT NULL,
+     PRIMARY KEY (category, name, version),
+     FOREIGN KEY (category, name, version) REFERENCES installed_packages (category, name, version) ON DELETE CASCADE
+     );
+
+     CREATE TABLE portage_sets (
+     portage_set       TEXT NOT NULL,
+     atom              TEXT NOT NULL,
+     category_and_name TEXT NOT NULL,
+     category          TEXT NOT NULL,
+     name              TEXT NOT NULL,
+     PRIMARY KEY (portage_set, atom),
+     FOREIGN KEY (category, name) REFERENCES packages (category, name) ON DELETE CASCADE
+     );
+     CREATE INDEX portage_sets_by_package ON portage_sets (category, name);
+
+     CREATE TABLE repo_hashes (
+     repo_path TEXT PRIMARY KEY,
+     head_hash TEXT NOT NULL
+     );
+
+     CREATE VIRTUAL TABLE packages_fts
+     USING fts5(category UNINDEXED, name, description, homepage, long_description, prefix = '2 3');",
+    // 2: repositories that are not git checkouts are fingerprinted by their timestamp files or directory mtimes.
+    "DELETE FROM repo_hashes;
+     ALTER TABLE repo_hashes ADD COLUMN fingerprint_kind TEXT NOT NULL DEFAULT 'git';",
+    // 3: the state of the installed package database at the time installed_packages was filled.
+    "CREATE TABLE vdb_fingerprints (
+     root        TEXT PRIMARY KEY,
+     fingerprint TEXT NOT NULL
+     );",
+    // 4: every available version is kept per repository, rather than only the first repository's copy.
+    "DROP TABLE upgradeable_packages;
+     DROP TABLE recommended_packages;
+     DROP TABLE ebuilds;
+     DROP TABLE all_packages;
+
+     CREATE TABLE all_packages (
+     category    TEXT NOT NULL,
+     name        TEXT NOT NULL,
+     version     TEXT NOT NULL,
+     repository  TEXT NOT NULL,
+     slot        TEXT NOT NULL,
+     keywords    TEXT NOT NULL,
+     description TEXT NOT NULL,
+     PRIMARY KEY (category, name, version, repository),
+     FOREIGN KEY (category, name) REFERENCES packages (category, name) ON DELETE CASCADE
+     );
+     CREATE INDEX all_packages_by_repository ON all_packages (repository, category, name);
+
+     CREATE TABLE recommended_packages (
+     category   TEXT NOT NULL,
+     name       TEXT NOT NULL,
+     version    TEXT NOT NULL,
+     repository TEXT NOT NULL,
+     PRIMARY KEY (category, name),
+     FOREIGN KEY (category, name, version, repository) REFERENCES all_packages (category, name, version, repository) ON DELETE CASCADE
+     );
+
+     CREATE TABLE ebuilds (
+     category    TEXT NOT NULL,
+     name        TEXT NOT NULL,
+     version     TEXT NOT NULL,
+     repository  TEXT NOT NULL,
+     ebuild_path TEXT NOT NULL,
+     PRIMARY KEY (category, name, version, repository),
+     FOREIGN KEY (category, name, version, repository) REFERENCES all_packages (category, name, version, repository) ON DELETE CASCADE
+     );
+
+     CREATE TABLE upgradeable_packages (
+     category          TEXT NOT NULL,
+     name              TEXT NOT NULL,
+     slot              TEXT NOT NULL,
+     installed_version TEXT NOT NULL,
+     version           TEXT NOT NULL,
+     repository        TEXT NOT NULL,
+     PRIMARY KEY (category, name, slot),
+     FOREIGN KEY (category, name, installed_version) REFERENCES installed_packages (category, name, version) ON DELETE CASCADE,
+     FOREIGN KEY (category, name, version, repository) REFERENCES all_packages (category, name, version, repository) ON DELETE CASCADE
+     );",
+    // 5: per-version visibility, shown when a package is expanded into its versions.
+    "ALTER TABLE all_packages ADD COLUMN arch_keyword TEXT NOT NULL DEFAULT '';
+     ALTER TABLE all_packages ADD COLUMN accepted INTEGER NOT NULL DEFAULT 0;
+
+     CREATE TABLE package_masks (
+     repository TEXT NOT NULL,
+     atom       TEXT NOT NULL,
+     category   TEXT NOT NULL,
+     name       TEXT NOT NULL,
+     PRIMARY KEY (repository, atom)
+     );
+     CREATE INDEX package_masks_by_package ON package_masks (category, name);",
+    // 6: the reverse dependency index, built from the dependencies of installed packages.
+    "CREATE TABLE reverse_dependencies (
+     category           TEXT NOT NULL,
+     name               TEXT NOT NULL,
+     atom               TEXT NOT NULL,
+     any_of             INTEGER NOT NULL,
+     kind               TEXT NOT NULL,
+     dependent_category TEXT NOT NULL,
+     dependent_name     TEXT NOT NULL,
+     dependent_version  TEXT NOT NULL,
+     PRIMARY KEY (dependent_category, dependent_name, dependent_version, kind, atom),
+     FOREIGN KEY (dependent_category, dependent_name, dependent_version) REFERENCES installed_packages (category, name, version) ON DELETE CASCADE
+     );
+     CREATE INDEX reverse_dependencies_by_package ON reverse_dependencies (category, name);",
+];
+
+/// Enables foreign keys, which SQLite leaves off for every new connection, and applies any outstanding migrations.
+///
+/// Returns `true` when a migration ran, in which case the cached package data has to be loaded again.
+pub fn migrate(conn: &Connection) -> bool {
+    conn.execute_batch("PRAGMA foreign_keys = ON").expect("failed to enable foreign keys");
+
+    let version = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get::<_, i32>(0)).expect("failed to read schema version") as usize;
+    if version > MIGRATIONS.len() {
+        panic!("database schema version {} is newer than this version of portix supports ({})", version, MIGRATIONS.len());
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        println!("Migrating database schema to version {}...", index + 1);
+        conn.execute_batch(&format!("BEGIN;
+                                     {}
+                                     PRAGMA user_version = {};
+                                     COMMIT;", migration, index + 1)).expect("failed to migrate database schema");
+    }
+    version < MIGRATIONS.len()
+}
@@ -0,0 +1,89 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Where portix keeps its database, reads portage's configuration and finds the installed packages, resolved from the
+/// command line and the environment.
+pub struct Settings {
+    pub db_path: PathBuf,
+    pub config_root: PathBuf,
+    /// The system whose installed package database is shown, which portage calls `ROOT`.
+    pub root: PathBuf,
+}
+
+impl Settings {
+    /// The database is taken from `--db <path>` (or `--db=<path>`), then `PORTIX_DB`, and otherwise lives at
+    /// `$XDG_CACHE_HOME/portix/portix.db`, with `~/.cache` standing in for an unset `XDG_CACHE_HOME`.
+    ///
+    /// The configuration is read below `--config-root <path>` (or `--config-root=<path>`), then `PORTAGE_CONFIGROOT`,
+    /// and otherwise `/`, the same as portage does. The installed packages are likewise read below `--root <path>`
+    /// (or `--root=<path>`), then `ROOT`, and otherwise `/`.
+    ///
+    /// The directory holding the database is created if it does not exist yet.
+    pub fn from_env() -> Settings {
+        let mut db_path = None;
+        let mut config_root = None;
+        let mut root = None;
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "--db" {
+                match args.next() {
+                    Some(path) => db_path = Some(PathBuf::from(path)),
+                    None => eprintln!("--db requires a path"),
+                }
+            } else if arg.starts_with("--db=") {
+                db_path = Some(PathBuf::from(&arg["--db=".len()..]));
+            } else if arg == "--config-root" {
+                match args.next() {
+                    Some(path) => config_root = Some(PathBuf::from(path)),
+                    None => eprintln!("--config-root requires a path"),
+                }
+            } else if arg.starts_with("--config-root=") {
+                config_root = Some(PathBuf::from(&arg["--config-root=".len()..]));
+            } else if arg == "--root" {
+                match args.next() {
+                    Some(path) => root = Some(PathBuf::from(path)),
+                    None => eprintln!("--root requires a path"),
+                }
+            } else if arg.starts_with("--root=") {
+                root = Some(PathBuf::from(&arg["--root=".len()..]));
+            } else {
+                eprintln!("ignoring unknown argument {}", arg);
+            }
+        }
+
+        let db_path = db_path.unwrap_or_else(|| match env::var_os("PORTIX_DB") {
+            Some(ref path) if !path.is_empty() => PathBuf::from(path),
+            _ => cache_dir().join("portix.db"),
+        });
+        if let Some(parent) = db_path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).expect("failed to create the database directory");
+            }
+        }
+
+        let config_root = config_root.unwrap_or_else(|| match env::var_os("PORTAGE_CONFIGROOT") {
+            Some(ref path) if !path.is_empty() => PathBuf::from(path),
+            _ => PathBuf::from("/"),
+        });
+
+        let root = root.unwrap_or_else(|| match env::var_os("ROOT") {
+            Some(ref path) if !path.is_empty() => PathBuf::from(path),
+            _ => PathBuf::from("/"),
+        });
+
+        Settings { db_path, config_root, root }
+    }
+}
+
+/// `$XDG_CACHE_HOME/portix`, falling back to `~/.cache/portix` when the variable is unset or not absolute.
+fn cache_dir() -> PathBuf {
+    let cache_home = match env::var_os("XDG_CACHE_HOME").map(PathBuf::from) {
+        Some(ref path) if path.is_absolute() => path.clone(),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".cache"),
+            None => env::temp_dir(),
+        },
+    };
+    cache_home.join("portix")
+}
@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::Command;
+
+use atom::Atom;
+use repos;
+use version::Version;
+
+const MAKE_CONF_PATH: &str = "etc/portage/make.conf";
+const PACKAGE_USE_PATH: &str = "etc/portage/package.use";
+
+/// The USE configuration every package is built with, read once per load as asking portage takes a while.
+pub struct UseConfig {
+    /// The flags enabled globally by the profile and `make.conf`, as portage reports them.
+    pub global: HashSet<String>,
+    /// Flags that `make.conf` disables, which also override a `+` default in IUSE.
+    pub disabled: HashSet<String>,
+    /// The `package.use` entries, in the order they apply.
+    pub package_use: Vec<(Atom, Vec<String>)>,
+}
+
+impl UseConfig {
+    /// The `package.use` flags that apply to a package version, in order. A later flag overrides an earlier one.
+    pub fn package_flags(&self, category: &str, name: &str, version: &Version, slot: &str, repository: &str) -> Vec<&str> {
+        self.package_use.iter()
+            .filter(|(atom, _)| atom.matches(category, name, version, slot, repository))
+            .flat_map(|(_, flags)| flags.iter().map(|flag| &**flag))
+            .collect()
+    }
+
+    /// Whether `flag` ends up enabled, given its IUSE `default` and the `package_flags` of the package.
+    pub fn enabled(&self, flag: &str, default: Option<bool>, package_flags: &[&str]) -> bool {
+        let mut enabled = self.global.contains(flag) || (default == Some(true) && !self.disabled.contains(flag));
+        for package_flag in package_flags {
+            if *package_flag == "-*" || (package_flag.starts_with('-') && &package_flag[1..] == flag) {
+                enabled = false;
+            } else if *package_flag == flag {
+                enabled = true;
+            }
+        }
+        enabled
+    }
+}
+
+/// Reads the USE configuration under `config_root`, asking portage for the global flags.
+pub fn read_use_config<P: AsRef<Path>>(config_root: P) -> UseConfig {
+    let config_root = config_root.as_ref();
+    UseConfig {
+        global: get_use(),
+        disabled: read_make_conf_disabled(config_root),
+        package_use: read_package_use(config_root),
+    }
+}
+
+/// Asks portage which USE flags are enabled globally, by the profile and `make.conf`.
+fn get_use() -> HashSet<String> {
+    let output = match Command::new("portageq").args(&["envvar", "USE"]).output() {
+        Ok(output) => output,
+        Err(why) => {
+            eprintln!("failed to get USE, so only IUSE defaults and package.use are shown: {}", why);
+            return HashSet::new();
+        }
+    };
+    String::from_utf8_lossy(&output.stdout).split_whitespace().map(|flag| flag.to_owned()).collect()
+}
+
+/// The `-flag`s of the `USE` assignments in `make.conf`, which may be a single file or a directory of files.
+fn read_make_conf_disabled(config_root: &Path) -> HashSet<String> {
+    let mut disabled = HashSet::new();
+    for path in repos::conf_files(&config_root.join(MAKE_CONF_PATH)) {
+        for flag in read_conf(&path).map_or_else(Vec::new, |text| use_assignments(&text)) {
+            if flag == "-*" {
+                disabled.clear();
+            } else if flag.starts_with('-') {
+                disabled.insert(flag[1..].to_owned());
+            } else {
+                disabled.remove(&flag);
+            }
+        }
+    }
+    disabled
+}
+
+/// The flags of every `USE="..."` assignment in `text`, whose value may span lines.
+fn use_assignments(text: &str) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if !line.starts_with("USE=") {
+            continue;
+        }
+        let mut value = line["USE=".len()..].to_owned();
+        if value.starts_with('"') || value.starts_with('\'') {
+            let quote = value.remove(0);
+            while !value.contains(quote) {
+                match lines.next() {
+                    Some(line) => {
+                        value.push(' ');
+                        value.push_str(line);
+                    }
+                    None => break,
+                }
+            }
+            value = value.split(quote).next().unwrap_or("").to_owned();
+        }
+        // `${USE}` and `$USE` only refer back to the earlier flags, which are already collected.
+        flags.extend(value.split_whitespace().filter(|flag| !flag.starts_with('$')).map(|flag| flag.to_owned()));
+    }
+    flags
+}
+
+/// Reads `package.use`, which may be a single file or a directory of files. Each line holds an atom followed by its
+/// flags, where a `USE_EXPAND:` such as `PYTHON_TARGETS:` prefixes the flags after it.
+fn read_package_use(config_root: &Path) -> Vec<(Atom, Vec<String>)> {
+    let mut entries = Vec::new();
+    for path in repos::conf_files(&config_root.join(PACKAGE_USE_PATH)) {
+        let text = match read_conf(&path) {
+            Some(text) => text,
+            None => continue,
+        };
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut tokens = line.split_whitespace();
+            let atom = match tokens.next().map(|atom| atom.parse::<Atom>()) {
+                Some(Ok(atom)) => atom,
+                Some(Err(why)) => {
+                    eprintln!("skipping {}: {}", path.display(), why);
+                    continue;
+                }
+                None => continue,
+            };
+
+            let mut prefix = String::new();
+            let mut flags = Vec::new();
+            for token in tokens {
+                if token.ends_with(':') {
+                    prefix = format!("{}_", token[..token.len() - 1].to_lowercase());
+                } else if token.starts_with('-') {
+                    flags.push(format!("-{}{}", prefix, &token[1..]));
+                } else {
+                    flags.push(format!("{}{}", prefix, token));
+                }
+            }
+            entries.push((atom, flags));
+        }
+    }
+    entries
+}
+
+fn read_conf(path: &Path) -> Option<String> {
+    let mut text = String::new();
+    match fs::File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+        Ok(_) => Some(text),
+        Err(why) => {
+            eprintln!("failed to read {}: {}", path.display(), why);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures;
+
+    fn fixture_config() -> UseConfig {
+        let config_root = fixtures::root();
+        UseConfig {
+            global: ["nls", "static"].iter().map(|flag| flag.to_string()).collect(),
+            disabled: read_make_conf_disabled(&config_root),
+            package_use: read_package_use(&config_root),
+        }
+    }
+
+    #[test]
+    fn applies_package_use_in_order() {
+        let config = fixture_config();
+        let flags = config.package_flags("app-misc", "hello", &"1.0".parse().unwrap(), "0", "gentoo");
+        assert_eq!(flags, vec!["-nls", "python_targets_python3_6"]);
+        assert!(!config.enabled("nls", Some(true), &flags));
+        assert!(config.enabled("static", None, &flags));
+        assert!(config.enabled("python_targets_python3_6", None, &flags));
+
+        let flags = config.package_flags("app-misc", "hello", &"2.0_rc1".parse().unwrap(), "0", "gentoo");
+        assert_eq!(flags, vec!["-nls", "python_targets_python3_6", "-*", "nls"]);
+        assert!(config.enabled("nls", None, &flags));
+        assert!(!config.enabled("static", None, &flags));
+    }
+
+    #[test]
+    fn make_conf_disables_iuse_defaults() {
+        let config = fixture_config();
+        assert_eq!(config.disabled, ["doc", "gtk"].iter().map(|flag| flag.to_string()).collect());
+        assert!(!config.enabled("doc", Some(true), &[]));
+        assert!(config.enabled("X", Some(true), &[]));
+        assert!(!config.enabled("X", None, &[]));
+    }
+}
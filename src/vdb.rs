@@ -0,0 +1,335 @@
+#[cfg(feature = "inotify")]
+extern crate inotify;
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use version::{self, Version};
+
+/// Location of the installed package database, relative to the root it describes.
+pub const VDB_PATH: &str = "var/db/pkg";
+
+/// Portage's global merge counter, bumped every time a package is merged.
+const COUNTER_PATH: &str = "var/cache/edb/counter";
+
+/// Seconds to wait between checks when the installed package database is polled.
+#[cfg(not(feature = "inotify"))]
+const POLL_INTERVAL: u64 = 5;
+
+/// A package recorded in the installed package database.
+pub struct InstalledPackage {
+    pub category: String,
+    pub name: String,
+    pub version: Version,
+    pub slot: String,
+    pub use_flags: String,
+    pub iuse: String,
+    pub keywords: String,
+    pub repository: String,
+    pub build_time: i64,
+    pub size: i64,
+    pub depend: String,
+    pub rdepend: String,
+    pub path: PathBuf,
+}
+
+/// Reads every `<category>/<pf>` entry of the installed package database under `root`.
+///
+/// Entries that are still being merged, or whose directory name does not carry a version, are skipped.
+pub fn read_installed_packages<P: AsRef<Path>>(root: P) -> Vec<InstalledPackage> {
+    let vdb_path = root.as_ref().join(VDB_PATH);
+    let mut packages = Vec::new();
+
+    let category_entries = match fs::read_dir(&vdb_path) {
+        Ok(entries) => entries,
+        Err(why) => {
+            eprintln!("failed to read installed package database {}: {}", vdb_path.display(), why);
+            return packages;
+        }
+    };
+    for category_entry in category_entries {
+        let category_path = category_entry.expect("intermittent IO error").path();
+        let category = category_path.file_name().unwrap().to_string_lossy().into_owned();
+        if !category_path.is_dir() || category.starts_with('.') {
+            continue;
+        }
+
+        for package_entry in fs::read_dir(&category_path).expect("category path does not exist") {
+            let package_path = package_entry.expect("intermittent IO error").path();
+            let pf = package_path.file_name().unwrap().to_string_lossy().into_owned();
+            if !package_path.is_dir() || pf.starts_with('.') || pf.starts_with("-MERGING-") {
+                continue;
+            }
+
+            let (name, version) = match version::split_pf(&pf) {
+                Some(split) => split,
+                None => continue,
+            };
+
+            packages.push(InstalledPackage {
+                category: category.clone(),
+                name: name.to_owned(),
+                version,
+                slot: read_entry(&package_path, "SLOT"),
+                use_flags: read_entry(&package_path, "USE"),
+                iuse: read_entry(&package_path, "IUSE"),
+                keywords: read_entry(&package_path, "KEYWORDS"),
+                repository: read_entry(&package_path, "repository"),
+                build_time: read_entry(&package_path, "BUILD_TIME").parse().unwrap_or(0),
+                size: read_entry(&package_path, "SIZE").parse().unwrap_or(0),
+                depend: read_entry(&package_path, "DEPEND"),
+                rdepend: read_entry(&package_path, "RDEPEND"),
+                path: package_path,
+            });
+        }
+    }
+    packages
+}
+
+/// A line of a package's `CONTENTS`, which lists everything the package installed.
+pub struct ContentsEntry {
+    /// `dir`, `obj`, `sym`, `fif` or `dev`.
+    pub kind: String,
+    pub path: String,
+    /// The checksum of an `obj`.
+    pub md5: Option<String>,
+    /// The modification time of an `obj` or `sym` when it was merged, in seconds since the epoch.
+    pub mtime: Option<u64>,
+    /// Where a `sym` points to.
+    pub target: Option<String>,
+}
+
+/// Reads the `CONTENTS` of the installed package at `package_path`, in the order portage recorded them.
+///
+/// Paths may contain spaces, so the fields following them are taken from the end of the line.
+pub fn read_contents(package_path: &Path) -> io::Result<Vec<ContentsEntry>> {
+    let mut contents_text = String::new();
+    fs::File::open(package_path.join("CONTENTS"))?.read_to_string(&mut contents_text)?;
+
+    let mut entries = Vec::new();
+    for line in contents_text.lines() {
+        let mut split = line.splitn(2, ' ');
+        let (kind, rest) = match (split.next(), split.next()) {
+            (Some(kind), Some(rest)) => (kind, rest),
+            _ => continue,
+        };
+        let mut entry = ContentsEntry { kind: kind.to_owned(), path: rest.to_owned(), md5: None, mtime: None, target: None };
+        match kind {
+            "obj" => {
+                let mut fields = rest.rsplitn(3, ' ');
+                if let (Some(mtime), Some(md5), Some(path)) = (fields.next(), fields.next(), fields.next()) {
+                    entry = ContentsEntry { path: path.to_owned(), md5: Some(md5.to_owned()), mtime: mtime.parse().ok(), ..entry };
+                }
+            }
+            "sym" => {
+                let mut fields = rest.rsplitn(2, ' ');
+                if let (Some(mtime), Some(link)) = (fields.next(), fields.next()) {
+                    if let Some(arrow) = link.find(" -> ") {
+                        entry = ContentsEntry {
+                            path: link[..arrow].to_owned(),
+                            target: Some(link[arrow + 4..].to_owned()),
+                            mtime: mtime.parse().ok(),
+                            ..entry
+                        };
+                    }
+                }
+            }
+            _ => (),
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Identifies the current state of the installed package database under `root`.
+///
+/// Combines portage's merge counter with the newest mtime of the database and its category directories, as merging
+/// or unmerging a package adds or removes an entry in its category directory.
+pub fn fingerprint<P: AsRef<Path>>(root: P) -> String {
+    let root = root.as_ref();
+    let counter = read_entry(root, COUNTER_PATH);
+    format!("{}:{}", counter, newest_directory_mtime(&root.join(VDB_PATH), 1))
+}
+
+/// The newest modification time, in nanoseconds since the epoch, of `path` and the directories up to `depth` levels
+/// below it.
+pub fn newest_directory_mtime(path: &Path, depth: usize) -> u64 {
+    let mtime = fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos()));
+    if depth == 0 {
+        return mtime;
+    }
+
+    fs::read_dir(path).map(|entries| {
+        entries.filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .fold(mtime, |newest, path| newest.max(newest_directory_mtime(&path, depth - 1)))
+    }).unwrap_or(mtime)
+}
+
+/// Blocks the calling thread, sending on `changes` whenever the installed package database under `root` may have
+/// changed, until the receiving end is dropped.
+///
+/// Uses inotify when built with the `inotify` feature, and polls the fingerprint otherwise.
+#[cfg(not(feature = "inotify"))]
+pub fn watch<P: AsRef<Path>>(root: P, changes: Sender<()>) {
+    let mut previous = fingerprint(&root);
+    loop {
+        thread::sleep(Duration::from_secs(POLL_INTERVAL));
+        let current = fingerprint(&root);
+        if current != previous {
+            previous = current;
+            if changes.send(()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Blocks the calling thread, sending on `changes` whenever the installed package database under `root` may have
+/// changed, until the receiving end is dropped.
+///
+/// Uses inotify when built with the `inotify` feature, and polls the fingerprint otherwise.
+#[cfg(feature = "inotify")]
+pub fn watch<P: AsRef<Path>>(root: P, changes: Sender<()>) {
+    use self::inotify::{Event, EventMask, Inotify, WatchDescriptor, WatchMask};
+    use std::ffi::OsStr;
+
+    fn watch_dir(inotify: &mut Inotify, path: &Path) -> Option<WatchDescriptor> {
+        let mask = WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_FROM | WatchMask::MOVED_TO;
+        inotify.add_watch(path, mask).map_err(|why| eprintln!("failed to watch {}: {}", path.display(), why)).ok()
+    }
+
+    /// Directories created in the database itself are new categories, which need watching too.
+    fn new_categories<'a, I: Iterator<Item = Event<&'a OsStr>>>(events: I, vdb_watch: &WatchDescriptor, vdb_path: &Path) -> Vec<PathBuf> {
+        events.filter(|event| event.wd == *vdb_watch && event.mask.contains(EventMask::CREATE | EventMask::ISDIR))
+            .filter_map(|event| event.name.map(|name| vdb_path.join(name)))
+            .collect()
+    }
+
+    let vdb_path = root.as_ref().join(VDB_PATH);
+    let mut inotify = match Inotify::init() {
+        Ok(inotify) => inotify,
+        Err(why) => {
+            eprintln!("failed to initialize inotify: {}", why);
+            return;
+        }
+    };
+
+    // Packages come and go in the category directories, while new categories show up in the database itself.
+    let vdb_watch = match watch_dir(&mut inotify, &vdb_path) {
+        Some(vdb_watch) => vdb_watch,
+        None => return,
+    };
+    if let Ok(entries) = fs::read_dir(&vdb_path) {
+        for category_path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| path.is_dir()) {
+            watch_dir(&mut inotify, &category_path);
+        }
+    }
+
+    let mut buffer = [0; 4096];
+    loop {
+        let mut categories = match inotify.read_events_blocking(&mut buffer) {
+            Ok(events) => new_categories(events, &vdb_watch, &vdb_path),
+            Err(why) => {
+                eprintln!("failed to read inotify events: {}", why);
+                return;
+            }
+        };
+
+        // A single merge produces a burst of events, so let it settle before reporting the change.
+        thread::sleep(Duration::from_secs(1));
+        loop {
+            match inotify.read_events(&mut buffer) {
+                Ok(events) => {
+                    let mut events = events.peekable();
+                    if events.peek().is_none() {
+                        break;
+                    }
+                    categories.extend(new_categories(events, &vdb_watch, &vdb_path));
+                }
+                Err(_) => break,
+            }
+        }
+
+        for category_path in categories {
+            watch_dir(&mut inotify, &category_path);
+        }
+        if changes.send(()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads a single-value file of a package entry, returning an empty string if it is absent.
+fn read_entry(package_path: &Path, key: &str) -> String {
+    let mut value = String::new();
+    match fs::File::open(package_path.join(key)).and_then(|mut file| file.read_to_string(&mut value)) {
+        Ok(_) => value.trim().to_owned(),
+        Err(_) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures;
+
+    #[test]
+    fn splits_names_containing_digits() {
+        let packages = read_installed_packages(fixtures::root());
+        assert_eq!(packages.len(), 2);
+
+        let font = packages.iter().find(|package| package.category == "media-fonts").expect("font is not installed");
+        assert_eq!(font.name, "font-adobe-100dpi");
+        assert_eq!(font.version.as_str(), "1.0.3");
+        assert_eq!(font.slot, "0");
+        assert_eq!(font.repository, "gentoo");
+        assert_eq!(font.build_time, 1_530_000_000);
+        assert_eq!(font.size, 1_048_576);
+    }
+
+    #[test]
+    fn reads_resolved_dependencies() {
+        let packages = read_installed_packages(fixtures::root());
+        let hello = packages.iter().find(|package| package.name == "hello").expect("hello is not installed");
+        assert_eq!(hello.version.as_str(), "1.0");
+        assert_eq!(hello.rdepend, "virtual/libintl");
+        assert_eq!(hello.depend, "virtual/libintl");
+    }
+
+    #[test]
+    fn reads_contents_from_the_end_of_the_line() {
+        let contents = read_contents(&fixtures::root().join(VDB_PATH).join("app-misc/hello-1.0")).expect("failed to read CONTENTS");
+        assert_eq!(contents.len(), 10);
+
+        let dir = &contents[0];
+        assert_eq!((dir.kind.as_str(), dir.path.as_str()), ("dir", "/usr"));
+        assert_eq!((dir.md5.as_ref(), dir.mtime, dir.target.as_ref()), (None, None, None));
+
+        let obj = &contents[2];
+        assert_eq!((obj.kind.as_str(), obj.path.as_str()), ("obj", "/usr/bin/hello"));
+        assert_eq!(obj.md5.as_ref().map(String::as_str), Some("0a1b2c3d4e5f60718293a4b5c6d7e8f9"));
+        assert_eq!(obj.mtime, Some(1_538_000_000));
+
+        let spaced = &contents[7];
+        assert_eq!(spaced.path, "/usr/share/doc/hello-1.0/Release Notes.bz2");
+        assert_eq!(spaced.md5.as_ref().map(String::as_str), Some("8f7e6d5c4b3a29180f1e2d3c4b5a6978"));
+        assert_eq!(spaced.mtime, Some(1_538_000_001));
+
+        let sym = &contents[8];
+        assert_eq!((sym.kind.as_str(), sym.path.as_str()), ("sym", "/usr/bin/hi"));
+        assert_eq!((sym.target.as_ref().map(String::as_str), sym.mtime, sym.md5.as_ref()), (Some("hello"), Some(1_538_000_000), None));
+
+        let spaced = &contents[9];
+        assert_eq!((spaced.path.as_str(), spaced.target.as_ref().map(String::as_str)), ("/usr/bin/hello world", Some("hello")));
+        assert_eq!(spaced.mtime, Some(1_538_000_002));
+    }
+}
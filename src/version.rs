@@ -0,0 +1,276 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Suffixes allowed after the numeric part of a version, in ascending order of precedence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Suffix {
+    Alpha,
+    Beta,
+    Pre,
+    Rc,
+    P,
+}
+
+impl Suffix {
+    fn from_str(suffix: &str) -> Option<Suffix> {
+        match suffix {
+            "alpha" => Some(Suffix::Alpha),
+            "beta" => Some(Suffix::Beta),
+            "pre" => Some(Suffix::Pre),
+            "rc" => Some(Suffix::Rc),
+            "p" => Some(Suffix::P),
+            _ => None,
+        }
+    }
+}
+
+/// A package version following the rules of the Package Manager Specification.
+///
+/// Comparison follows PMS section 3.3, so `1.0_rc1 < 1.0 < 1.0-r1 < 1.0_p1 < 1.0a`.
+#[derive(Clone, Debug)]
+pub struct Version {
+    text: String,
+    numbers: Vec<String>,
+    letter: Option<char>,
+    suffixes: Vec<(Suffix, String)>,
+    revision: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ParseVersionError(String);
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid version: {}", self.0)
+    }
+}
+
+impl Version {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The same version with its `-rN` revision dropped, as used by the `~` atom operator.
+    pub fn without_revision(&self) -> Version {
+        let text = match self.revision {
+            Some(ref revision) => self.text[..self.text.len() - revision.len() - 2].to_owned(),
+            None => self.text.clone(),
+        };
+        Version { text, revision: None, ..self.clone() }
+    }
+
+    /// The revision number, `0` when the version has none.
+    pub fn revision(&self) -> &str {
+        self.revision.as_ref().map_or("0", |revision| revision)
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseVersionError;
+
+    fn from_str(text: &str) -> Result<Version, ParseVersionError> {
+        let error = || ParseVersionError(text.to_owned());
+        let (rest, revision) = match text.rfind("-r") {
+            Some(index) => {
+                let revision = &text[index + 2..];
+                if revision.is_empty() || !revision.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(error());
+                }
+                (&text[..index], Some(revision.to_owned()))
+            }
+            None => (text, None),
+        };
+
+        let mut parts = rest.split('_');
+        let base = parts.next().ok_or_else(error)?;
+        let (base, letter) = match base.chars().last() {
+            Some(c) if c.is_ascii_lowercase() => (&base[..base.len() - 1], Some(c)),
+            _ => (base, None),
+        };
+
+        let numbers: Vec<String> = base.split('.').map(|number| number.to_owned()).collect();
+        if numbers.iter().any(|number| number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit())) {
+            return Err(error());
+        }
+
+        let mut suffixes = Vec::new();
+        for part in parts {
+            let split = part.find(|c: char| c.is_ascii_digit()).unwrap_or(part.len());
+            let suffix = Suffix::from_str(&part[..split]).ok_or_else(error)?;
+            if !part[split..].bytes().all(|b| b.is_ascii_digit()) {
+                return Err(error());
+            }
+            suffixes.push((suffix, part[split..].to_owned()));
+        }
+
+        Ok(Version { text: text.to_owned(), numbers, letter, suffixes, revision })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        let ordering = compare_integers(&self.numbers[0], &other.numbers[0]);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        for (a, b) in self.numbers[1..].iter().zip(other.numbers[1..].iter()) {
+            let ordering = if a.starts_with('0') || b.starts_with('0') {
+                a.trim_end_matches('0').cmp(b.trim_end_matches('0'))
+            } else {
+                compare_integers(a, b)
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        let ordering = self.numbers.len().cmp(&other.numbers.len());
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        let ordering = self.letter.cmp(&other.letter);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        for (&(a, ref a_number), &(b, ref b_number)) in self.suffixes.iter().zip(other.suffixes.iter()) {
+            let ordering = a.cmp(&b).then_with(|| compare_integers(a_number, b_number));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        let ordering = match self.suffixes.len().cmp(&other.suffixes.len()) {
+            Ordering::Greater => compare_to_missing_suffix(self.suffixes[other.suffixes.len()].0),
+            Ordering::Less => compare_to_missing_suffix(other.suffixes[self.suffixes.len()].0).reverse(),
+            Ordering::Equal => Ordering::Equal,
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        compare_integers(self.revision(), other.revision())
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// Only `_p` sorts after a version that lacks the suffix entirely.
+fn compare_to_missing_suffix(suffix: Suffix) -> Ordering {
+    if suffix == Suffix::P { Ordering::Greater } else { Ordering::Less }
+}
+
+/// Compares two strings of ASCII digits by numeric value, regardless of their length.
+fn compare_integers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Splits a `<name>-<version>` string at the first hyphen that is followed by a valid version.
+pub fn split_pf(pf: &str) -> Option<(&str, Version)> {
+    pf.match_indices('-')
+        .filter(|&(index, _)| index > 0)
+        .filter_map(|(index, _)| pf[index + 1..].parse().ok().map(|version| (&pf[..index], version)))
+        .next()
+}
+
+/// Splits an ebuild file name such as `gtk+-3.24.1-r1.ebuild` into its package name and version.
+pub fn from_ebuild_filename(file_name: &str) -> Option<(&str, Version)> {
+    if file_name.ends_with(".ebuild") {
+        split_pf(&file_name[..file_name.len() - ".ebuild".len()])
+    } else {
+        None
+    }
+}
+
+/// Collation used by `ORDER BY version COLLATE gentoo_version`.
+///
+/// Text that is not a valid version falls back to plain string ordering.
+pub fn collate(a: &str, b: &str) -> Ordering {
+    match (a.parse::<Version>(), b.parse::<Version>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> Version {
+        text.parse().unwrap_or_else(|why| panic!("{}", why))
+    }
+
+    #[test]
+    fn orders_versions() {
+        let table = [
+            ("1.0_rc1", Ordering::Less, "1.0"),
+            ("1.0", Ordering::Less, "1.0-r1"),
+            ("1.0-r1", Ordering::Less, "1.0_p1"),
+            ("1.0_p1", Ordering::Less, "1.0a"),
+            ("1.01", Ordering::Less, "1.1"),
+            ("1.0", Ordering::Equal, "1.00"),
+            ("1.0", Ordering::Less, "1.0.0"),
+            ("1.0_alpha1_p2", Ordering::Greater, "1.0_alpha1"),
+            ("1.0_alpha1", Ordering::Greater, "1.0_alpha1_beta1"),
+            ("1.0_alpha2", Ordering::Less, "1.0_beta1"),
+            ("1.0-r0", Ordering::Equal, "1.0"),
+            ("1.0-r2", Ordering::Less, "1.0-r10"),
+            ("9999", Ordering::Greater, "2018.10.1_p20181018-r3"),
+            ("10", Ordering::Greater, "9.99"),
+        ];
+        for &(a, ordering, b) in &table {
+            assert_eq!(version(a).cmp(&version(b)), ordering, "{} {:?} {}", a, ordering, b);
+            assert_eq!(version(b).cmp(&version(a)), ordering.reverse(), "{} {:?} {}", b, ordering.reverse(), a);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        for text in &["", "1.", ".1", "1.0_gamma1", "1.0-r", "1.0ab", "a1.0", "1.0_p1a"] {
+            assert!(text.parse::<Version>().is_err(), "{} parsed", text);
+        }
+    }
+
+    #[test]
+    fn splits_package_names_from_versions() {
+        let (name, version) = split_pf("gtk+-3.24.1-r1").unwrap();
+        assert_eq!(name, "gtk+");
+        assert_eq!(version.as_str(), "3.24.1-r1");
+        assert_eq!(version.revision(), "1");
+
+        let (name, version) = split_pf("font-adobe-100dpi-1.0.3").unwrap();
+        assert_eq!(name, "font-adobe-100dpi");
+        assert_eq!(version.as_str(), "1.0.3");
+
+        assert!(split_pf("font-adobe-100dpi").is_none());
+        assert_eq!(from_ebuild_filename("gtk+-3.24.1-r1.ebuild").map(|(name, _)| name), Some("gtk+"));
+        assert!(from_ebuild_filename("metadata.xml").is_none());
+    }
+
+    #[test]
+    fn drops_revisions() {
+        assert_eq!(version("1.2.3-r4").without_revision().as_str(), "1.2.3");
+        assert_eq!(version("1.2.3").without_revision().as_str(), "1.2.3");
+    }
+}